@@ -0,0 +1,133 @@
+//! Benchmarks the cache hits under contention, no etcd server is needed.
+//!
+//! `Cache::search` records each hit in the eviction policy. The lock-free `LruPolicy` is
+//! compared with a baseline policy behind a lock, which is how the cache recorded the hits
+//! in a `Mutex<PriorityQueue>` before. The keys are cached as absent, so no value is
+//! cloned and the cost of recording the hits stands out.
+//! Run it by `cargo bench --bench cache`.
+use clippy_utilities::{Cast, OverflowArithmetic};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use etcd_client::{Cache, CacheCapacity, EvictionPolicy, LruPolicy};
+use priority_queue::PriorityQueue;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of cached keys.
+const KEYS: usize = 1024;
+
+/// The LRU policy keeping the keys in a priority queue behind a lock.
+struct LockedLruPolicy {
+    /// The keys by the priority, the least recently used key has the highest priority.
+    queue: Mutex<PriorityQueue<Vec<u8>, u64>>,
+    /// The number of the inserts and the hits, which decreases the priority.
+    tick: AtomicU64,
+}
+
+impl LockedLruPolicy {
+    /// Creates a new `LockedLruPolicy`.
+    fn new() -> Self {
+        Self {
+            queue: Mutex::new(PriorityQueue::new()),
+            tick: AtomicU64::new(0),
+        }
+    }
+
+    /// Gets the priority of a key used now.
+    fn priority(&self) -> u64 {
+        u64::MAX.overflow_sub(self.tick.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Locks the queue.
+    fn queue(&self) -> std::sync::MutexGuard<'_, PriorityQueue<Vec<u8>, u64>> {
+        self.queue
+            .lock()
+            .unwrap_or_else(|e| panic!("Fail to lock the queue, the error is: {}", e))
+    }
+}
+
+impl EvictionPolicy for LockedLruPolicy {
+    fn on_insert(&self, key: &[u8]) {
+        let priority = self.priority();
+        let _prev = self.queue().push(key.to_vec(), priority);
+    }
+
+    fn on_hit(&self, key: &[u8]) {
+        let priority = self.priority();
+        let _prev = self.queue().change_priority(key, priority);
+    }
+
+    fn on_remove(&self, key: &[u8]) {
+        let _removed = self.queue().remove(key);
+    }
+
+    fn victim(&self) -> Option<Vec<u8>> {
+        self.queue().pop().map(|(key, _)| key)
+    }
+}
+
+/// Creates a cache of the policy holding all the keys.
+fn cache(policy: Box<dyn EvictionPolicy>, keys: &[Vec<u8>]) -> Cache {
+    let capacity = KEYS.overflow_mul(2);
+    let cache = Cache::new(CacheCapacity::Entries(capacity), policy, capacity);
+    for key in keys {
+        let _marked = cache.mark_absent(key.clone(), 1);
+    }
+    cache
+}
+
+/// Searches the cached keys by several threads at the same time.
+fn bench_search(c: &mut Criterion) {
+    let keys = (0..KEYS)
+        .map(|i| format!("key{}", i).into_bytes())
+        .collect::<Vec<_>>();
+    let caches = [
+        ("lock_free_lru", cache(Box::new(LruPolicy::new()), &keys)),
+        ("locked_lru", cache(Box::new(LockedLruPolicy::new()), &keys)),
+    ];
+
+    for (name, cache) in &caches {
+        let mut group = c.benchmark_group(format!("search/{}", name));
+        for threads in [1_usize, 2, 4, 8, 16] {
+            // Each iteration searches a key by every thread
+            group.throughput(Throughput::Elements(threads.cast()));
+            group.bench_with_input(
+                BenchmarkId::from_parameter(threads),
+                &threads,
+                |b, &threads| {
+                    b.iter_custom(|iters| {
+                        let start = Instant::now();
+                        thread::scope(|s| {
+                            for t in 0..threads {
+                                let keys = &keys;
+                                s.spawn(move || {
+                                    for i in 0..iters {
+                                        let index = t.overflow_add(i.cast()).overflow_rem(KEYS);
+                                        let key = keys.get(index).unwrap_or_else(|| {
+                                            panic!("Fail to get the key {}", index)
+                                        });
+                                        assert!(
+                                            cache.search(key).is_some(),
+                                            "The key {} should be cached",
+                                            index
+                                        );
+                                    }
+                                });
+                            }
+                        });
+                        start.elapsed()
+                    });
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(10));
+    targets = bench_search
+}
+criterion_main!(benches);
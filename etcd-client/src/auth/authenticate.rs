@@ -1,4 +1,5 @@
 use crate::protos::rpc::{AuthenticateRequest, AuthenticateResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 
 /// Request for authenticating.
@@ -6,6 +7,8 @@ use crate::ResponseHeader;
 pub struct EtcdAuthenticateRequest {
     /// Etcd authenticate request.
     proto: AuthenticateRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdAuthenticateRequest {
@@ -21,7 +24,22 @@ impl EtcdAuthenticateRequest {
             password: password.into(),
             ..AuthenticateRequest::default()
         };
-        Self { proto }
+        Self {
+            proto,
+            options: CallOptions::default(),
+        }
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
     }
 }
 
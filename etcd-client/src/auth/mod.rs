@@ -1,31 +1,39 @@
 /// Authenticate mode for Etcd authentication operations.
 mod authenticate;
+/// Token mod for attaching auth token to RPCs.
+mod token;
 
 pub use authenticate::{EtcdAuthenticateRequest, EtcdAuthenticateResponse};
+pub(crate) use token::{AuthToken, INVALID_TOKEN_REASON};
 
 use crate::protos::rpc_grpc::AuthClient;
-use crate::retryable;
 use crate::Result;
-use crate::CURRENT_INTERVAL_ENV_KEY;
-use crate::CURRENT_INTERVAL_VALUE;
-use crate::INITIAL_INTERVAL_ENV_KEY;
-use crate::INITIAL_INTERVAL_VALUE;
-use crate::MAX_ELAPSED_TIME_ENV_KEY;
-use crate::MAX_ELAPSED_TIME_VALUE;
-use backoff::ExponentialBackoff;
-use std::time::Duration;
+use crate::{CallOptions, RetryOperation, RetryPolicy};
+use std::sync::Arc;
 
 /// Auth client which provides authenticating operation.
 #[derive(Clone)]
 pub struct Auth {
     /// Etcd Auth client.
     client: AuthClient,
+    /// Auth token attached to RPCs.
+    token: Arc<AuthToken>,
+    /// Retry policy of RPCs.
+    retry_policy: Arc<RetryPolicy>,
 }
 
 impl Auth {
     /// Creates a new Auth client.
-    pub(crate) const fn new(client: AuthClient) -> Self {
-        Self { client }
+    pub(crate) const fn new(
+        client: AuthClient,
+        token: Arc<AuthToken>,
+        retry_policy: Arc<RetryPolicy>,
+    ) -> Self {
+        Self {
+            client,
+            token,
+            retry_policy,
+        }
     }
 
     /// Performs an authenticating operation.
@@ -37,10 +45,17 @@ impl Auth {
         &mut self,
         req: EtcdAuthenticateRequest,
     ) -> Result<EtcdAuthenticateResponse> {
-        let authenticate_result = retryable!(|| async {
-            let resp = self.client.authenticate_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
+        let options = req.call_options().start();
+        let authenticate_result = self
+            .retry_policy
+            .retry(RetryOperation::Authenticate, options.deadline, || async {
+                let resp = self.client.authenticate_async_opt(
+                    &req.clone().into(),
+                    self.token.call_option(&options)?,
+                )?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
         Ok(authenticate_result)
     }
 }
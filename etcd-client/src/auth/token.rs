@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use grpcio::{CallOption, MetadataBuilder, RpcStatusCode};
+use log::warn;
+use smol::lock::Mutex;
+
+use super::EtcdAuthenticateRequest;
+use crate::protos::rpc_grpc::AuthClient;
+use crate::{CallOptions, OverflowArithmetic};
+
+/// The metadata key of the auth token, which is checked by etcd server.
+const TOKEN_METADATA_KEY: &str = "token";
+/// The reason etcd server gives when it rejects a watch for an invalid or expired token.
+pub(crate) const INVALID_TOKEN_REASON: &str = "invalid auth token";
+
+/// Authentication token shared by all the clients of an etcd client,
+/// which is attached to every RPC as call metadata.
+pub(crate) struct AuthToken {
+    /// Etcd Auth client used to generate the token.
+    client: AuthClient,
+    /// User name and password, no token is generated if it is `None`.
+    credential: Option<(String, String)>,
+    /// Current auth token.
+    token: ArcSwapOption<String>,
+    /// Lock to prevent re-authenticating concurrently.
+    refresh_lock: Mutex<()>,
+}
+
+impl AuthToken {
+    /// Creates a new `AuthToken`.
+    pub(crate) fn new(client: AuthClient, credential: Option<(String, String)>) -> Self {
+        Self {
+            client,
+            credential,
+            token: ArcSwapOption::empty(),
+            refresh_lock: Mutex::new(()),
+        }
+    }
+
+    /// Authenticates with the user name and password and stores the new token.
+    /// Does nothing if no credential is configured.
+    pub(crate) async fn authenticate(&self) -> grpcio::Result<()> {
+        if let Some((ref name, ref password)) = self.credential {
+            let req = EtcdAuthenticateRequest::new(name.as_str(), password.as_str());
+            let resp = self.client.authenticate_async(&req.into())?.await?;
+            self.token
+                .store(Some(Arc::new(resp.get_token().to_owned())));
+        }
+        Ok(())
+    }
+
+    /// Builds the `CallOption` which carries the current token and the per-request options.
+    pub(crate) fn call_option(&self, options: &CallOptions) -> grpcio::Result<CallOption> {
+        let token = self.token.load_full();
+        let mut builder = MetadataBuilder::with_capacity(options.metadata.len().overflow_add(1));
+        for &(ref key, ref value) in &options.metadata {
+            builder.add_str(key, value)?;
+        }
+        if let Some(ref token) = token {
+            builder.add_str(TOKEN_METADATA_KEY, token.as_str())?;
+        }
+        let mut option = CallOption::default()
+            .wait_for_ready(options.wait_for_ready)
+            .headers(builder.build());
+        if let Some(timeout) = options.remaining() {
+            option = option.timeout(timeout);
+        }
+        Ok(option)
+    }
+
+    /// Checks the result of an RPC, re-authenticates if the server
+    /// answers that the token is invalid or expired.
+    /// The original result is returned so that the retry policy of the caller retries the RPC.
+    pub(crate) async fn check<T>(&self, res: grpcio::Result<T>) -> grpcio::Result<T> {
+        if let Err(ref e) = res {
+            let _refreshed = self.refresh_on(e).await;
+        }
+        res
+    }
+
+    /// Calls an RPC with the current token, and calls it once more with the new token if
+    /// the server answers that the token is invalid or expired and it is refreshed.
+    /// It is for the RPCs not retried by a retry policy.
+    pub(crate) async fn call<T, F, Fut>(&self, options: &CallOptions, call: F) -> grpcio::Result<T>
+    where
+        F: Fn(CallOption) -> grpcio::Result<Fut>,
+        Fut: Future<Output = grpcio::Result<T>>,
+    {
+        let res = call(self.call_option(options)?)?.await;
+        match res {
+            Err(ref e) if self.refresh_on(e).await => call(self.call_option(options)?)?.await,
+            _ => res,
+        }
+    }
+
+    /// Re-authenticates if the error means the token is invalid or expired,
+    /// returns `true` if a new token is available.
+    pub(crate) async fn refresh_on(&self, err: &grpcio::Error) -> bool {
+        Self::is_invalid_token(err) && self.refresh().await
+    }
+
+    /// Re-authenticates, skips if some other task has already refreshed the token.
+    /// Returns `true` if a new token is available.
+    pub(crate) async fn refresh(&self) -> bool {
+        if self.credential.is_none() {
+            return false;
+        }
+        let stale = self.token.load_full();
+        let _guard = self.refresh_lock.lock().await;
+        let current = self.token.load_full();
+        let refreshed = match (stale, current) {
+            (Some(ref stale), Some(ref current)) => !Arc::ptr_eq(stale, current),
+            (None, Some(_)) => true,
+            (_, None) => false,
+        };
+        if refreshed {
+            return true;
+        }
+        match self.authenticate().await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Fail to refresh auth token, the error is: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns if the error means the auth token is invalid or expired.
+    fn is_invalid_token(err: &grpcio::Error) -> bool {
+        if let grpcio::Error::RpcFailure(ref status) = *err {
+            status.code() == RpcStatusCode::UNAUTHENTICATED
+        } else {
+            false
+        }
+    }
+}
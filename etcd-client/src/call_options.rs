@@ -0,0 +1,109 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use smol::Timer;
+
+use crate::{EtcdError, Result};
+
+/// Per-request options of a unary RPC.
+///
+/// The deadline bounds the whole request, including retries. When it expires the request
+/// fails with [`EtcdError::DeadlineExceeded`]. Dropping the returned future cancels the
+/// in-flight RPC.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallOptions {
+    /// The timeout of the request, counted from the time the request is sent.
+    pub timeout: Option<Duration>,
+    /// The absolute deadline of the request.
+    pub deadline: Option<Instant>,
+    /// Wait for the connection to be ready instead of failing fast.
+    pub wait_for_ready: bool,
+    /// The extra metadata attached to the request.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl CallOptions {
+    /// Creates a new `CallOptions` without deadline and metadata.
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timeout of the request.
+    #[must_use]
+    #[inline]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the absolute deadline of the request.
+    #[must_use]
+    #[inline]
+    pub const fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets whether to wait for the connection to be ready.
+    #[must_use]
+    #[inline]
+    pub const fn with_wait_for_ready(mut self, wait_for_ready: bool) -> Self {
+        self.wait_for_ready = wait_for_ready;
+        self
+    }
+
+    /// Adds a metadata entry to the request.
+    #[must_use]
+    #[inline]
+    pub fn with_metadata<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Fixes the deadline of a request sent now, the earlier one of
+    /// `deadline` and `now + timeout` is taken.
+    pub(crate) fn start(&self) -> Self {
+        let deadline = self
+            .timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+        Self {
+            timeout: None,
+            deadline: match (self.deadline, deadline) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+            wait_for_ready: self.wait_for_ready,
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// The time left before the deadline.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// Runs `fut` until the deadline expires.
+pub(crate) async fn with_deadline<T, F>(deadline: Option<Instant>, fut: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    match deadline {
+        Some(deadline) => {
+            smol::future::or(fut, async {
+                Timer::at(deadline).await;
+                Err(EtcdError::DeadlineExceeded)
+            })
+            .await
+        }
+        None => fut.await,
+    }
+}
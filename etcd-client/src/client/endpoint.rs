@@ -0,0 +1,384 @@
+//! The parser of etcd endpoints.
+//!
+//! An endpoint can be in one of the following forms:
+//! - `127.0.0.1:2379`, `[::1]:2379`, `etcd-0:2379` or `etcd-0`
+//! - `http://etcd-0:2379` or `https://etcd-0:2379`, the scheme decides whether to use TLS
+//! - `unix:///var/run/etcd.sock`, `unix:etcd.sock` or `unixs:///var/run/etcd.sock`
+//!
+//! A unix domain socket must be the only endpoint, while several ip or host name
+//! endpoints are balanced by the client.
+
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use crate::{EtcdError, Result};
+
+/// The default port of etcd client requests.
+const DEFAULT_PORT: u16 = 2379;
+
+/// The address of an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Address {
+    /// An ip socket address.
+    Ip(SocketAddr),
+    /// A host name resolved by DNS and its port.
+    Dns(String, u16),
+    /// A unix domain socket path.
+    Unix(String),
+}
+
+/// A parsed etcd endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    /// The address of the endpoint.
+    address: Address,
+    /// Whether the scheme requires TLS, `None` if no scheme is specified.
+    secure: Option<bool>,
+}
+
+impl Endpoint {
+    /// Parse an endpoint.
+    fn parse(endpoint: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            EtcdError::InvalidEndpoint(format!("{}, the endpoint is {}", reason, endpoint))
+        };
+
+        for (scheme, secure) in [("unixs:", true), ("unix:", false)] {
+            if let Some(path) = endpoint.strip_prefix(scheme) {
+                let path = path.strip_prefix("//").unwrap_or(path);
+                if path.is_empty() {
+                    return Err(invalid("empty unix domain socket path"));
+                }
+                return Ok(Self {
+                    address: Address::Unix(path.to_owned()),
+                    secure: Some(secure),
+                });
+            }
+        }
+
+        let (secure, rest) = if let Some(rest) = endpoint.strip_prefix("https://") {
+            (Some(true), rest)
+        } else if let Some(rest) = endpoint.strip_prefix("http://") {
+            (Some(false), rest)
+        } else if endpoint.contains("://") {
+            return Err(invalid("unsupported scheme"));
+        } else {
+            (None, endpoint)
+        };
+        // Ignore the path of a URL, such as the trailing `/`
+        let authority = rest
+            .split_once('/')
+            .map_or(rest, |(authority, _)| authority);
+        if authority.is_empty() {
+            return Err(invalid("empty host"));
+        }
+
+        let address = if let Ok(addr) = authority.parse::<SocketAddr>() {
+            Address::Ip(addr)
+        } else if let Some(ip) = authority
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+        {
+            let ip = ip
+                .parse::<IpAddr>()
+                .map_err(|e| invalid(&format!("invalid ipv6 address, the error is {}", e)))?;
+            Address::Ip(SocketAddr::new(ip, DEFAULT_PORT))
+        } else {
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    port.parse::<u16>()
+                        .map_err(|e| invalid(&format!("invalid port, the error is {}", e)))?,
+                ),
+                None => (authority, DEFAULT_PORT),
+            };
+            if host.is_empty() {
+                return Err(invalid("empty host"));
+            }
+            match host.parse::<IpAddr>() {
+                Ok(ip) => Address::Ip(SocketAddr::new(ip, port)),
+                Err(_) => Address::Dns(host.to_owned(), port),
+            }
+        };
+
+        Ok(Self { address, secure })
+    }
+}
+
+/// The grpc target built from etcd endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Target {
+    /// The grpc target address.
+    pub(crate) address: String,
+    /// Whether the endpoint schemes require TLS, `None` if no scheme is specified.
+    pub(crate) secure: Option<bool>,
+    /// The distinct host names resolved to ip addresses by the client, the server
+    /// certificate is verified against the host name if there is only one.
+    pub(crate) host_names: Vec<String>,
+}
+
+impl Target {
+    /// Resolve the endpoints and build the grpc target.
+    ///
+    /// A single host name is resolved by the DNS resolver of grpc, which balances the load
+    /// among all its addresses and resolves it again when the addresses change. When there
+    /// are several endpoints, as etcdctl does, each host name is resolved by the client
+    /// when connecting, and all the addresses are balanced by the client.
+    pub(crate) async fn resolve(endpoints: &[String]) -> Result<Self> {
+        let endpoints = endpoints
+            .iter()
+            .map(|endpoint| Endpoint::parse(endpoint))
+            .collect::<Result<Vec<_>>>()?;
+        let secure = Self::secure(&endpoints)?;
+
+        match *endpoints.as_slice() {
+            [] => Err(EtcdError::InvalidEndpoint(
+                "empty etcd endpoints".to_owned(),
+            )),
+            [Endpoint {
+                address: Address::Dns(ref host, port),
+                ..
+            }] => Ok(Self {
+                address: format!("dns:///{}:{}", host, port),
+                secure,
+                host_names: Vec::new(),
+            }),
+            [Endpoint {
+                address: Address::Unix(ref path),
+                ..
+            }] => Ok(Self {
+                address: format!("unix:{}", path),
+                secure,
+                host_names: Vec::new(),
+            }),
+            _ => {
+                let mut host_names = Vec::new();
+                let mut addresses = Vec::with_capacity(endpoints.len());
+                for endpoint in endpoints {
+                    match endpoint.address {
+                        Address::Ip(addr) => addresses.push(addr),
+                        Address::Dns(host, port) => {
+                            addresses.extend(Self::lookup(host.clone(), port).await?);
+                            if !host_names.contains(&host) {
+                                host_names.push(host);
+                            }
+                        }
+                        Address::Unix(_) => {
+                            return Err(EtcdError::InvalidEndpoint(
+                                "unix domain socket endpoint can't be used with other endpoints"
+                                    .to_owned(),
+                            ));
+                        }
+                    }
+                }
+                Ok(Self {
+                    address: Self::ip_target(&addresses)?,
+                    secure,
+                    host_names,
+                })
+            }
+        }
+    }
+
+    /// Check the endpoints don't mix secure and insecure schemes.
+    fn secure(endpoints: &[Endpoint]) -> Result<Option<bool>> {
+        let mut secure = None;
+        for endpoint in endpoints {
+            match (secure, endpoint.secure) {
+                (Some(current), Some(other)) if current != other => {
+                    return Err(EtcdError::InvalidEndpoint(
+                        "endpoints have both secure and insecure schemes".to_owned(),
+                    ));
+                }
+                (None, Some(other)) => secure = Some(other),
+                _ => {}
+            }
+        }
+        Ok(secure)
+    }
+
+    /// Resolve a host name to ip addresses, prefer ipv4 addresses if there are any.
+    async fn lookup(host: String, port: u16) -> Result<Vec<SocketAddr>> {
+        let addresses = smol::unblock(move || {
+            let addresses = (host.as_str(), port)
+                .to_socket_addrs()
+                .map(Iterator::collect::<Vec<_>>)
+                .map_err(|e| {
+                    EtcdError::InvalidEndpoint(format!(
+                        "fail to resolve host {}, the error is {}",
+                        host, e
+                    ))
+                })?;
+            if addresses.is_empty() {
+                return Err(EtcdError::InvalidEndpoint(format!(
+                    "no address is resolved from host {}",
+                    host
+                )));
+            }
+            Ok(addresses)
+        })
+        .await?;
+        let ipv4_addresses = addresses
+            .iter()
+            .copied()
+            .filter(SocketAddr::is_ipv4)
+            .collect::<Vec<_>>();
+        if ipv4_addresses.is_empty() {
+            Ok(addresses)
+        } else {
+            Ok(ipv4_addresses)
+        }
+    }
+
+    /// Build the target of multiple ip addresses, which must be in the same family.
+    fn ip_target(addresses: &[SocketAddr]) -> Result<String> {
+        let schema = if addresses.iter().all(SocketAddr::is_ipv4) {
+            "ipv4"
+        } else if addresses.iter().all(SocketAddr::is_ipv6) {
+            "ipv6"
+        } else {
+            return Err(EtcdError::InvalidEndpoint(
+                "endpoints have different type of ip address schema".to_owned(),
+            ));
+        };
+        let addresses = addresses
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(format!("{}:{}", schema, addresses))
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(endpoints: &[&str]) -> Result<Target> {
+        let endpoints = endpoints
+            .iter()
+            .map(|endpoint| (*endpoint).to_owned())
+            .collect::<Vec<_>>();
+        smol::block_on(Target::resolve(&endpoints))
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        let ip = |addr: &str| Address::Ip(addr.parse().unwrap());
+        let cases = [
+            ("127.0.0.1:2379", ip("127.0.0.1:2379"), None),
+            ("127.0.0.1", ip("127.0.0.1:2379"), None),
+            ("http://127.0.0.1:2379", ip("127.0.0.1:2379"), Some(false)),
+            ("https://127.0.0.1:2380/", ip("127.0.0.1:2380"), Some(true)),
+            ("[::1]:2379", ip("[::1]:2379"), None),
+            ("https://[::1]", ip("[::1]:2379"), Some(true)),
+            (
+                "http://etcd-0:2379",
+                Address::Dns("etcd-0".to_owned(), 2379),
+                Some(false),
+            ),
+            ("etcd-0", Address::Dns("etcd-0".to_owned(), 2379), None),
+            (
+                "unix:///var/run/etcd.sock",
+                Address::Unix("/var/run/etcd.sock".to_owned()),
+                Some(false),
+            ),
+            (
+                "unixs:etcd.sock",
+                Address::Unix("etcd.sock".to_owned()),
+                Some(true),
+            ),
+        ];
+        for (endpoint, address, secure) in cases {
+            assert_eq!(
+                Endpoint::parse(endpoint).unwrap(),
+                Endpoint { address, secure },
+                "Fail to parse endpoint {}",
+                endpoint
+            );
+        }
+
+        for endpoint in [
+            "",
+            "http://",
+            "grpc://etcd-0:2379",
+            "etcd-0:port",
+            "unix://",
+        ] {
+            assert!(
+                Endpoint::parse(endpoint).is_err(),
+                "Endpoint {} should be invalid",
+                endpoint
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_target() {
+        let target = resolve(&["http://127.0.0.1:2379", "http://127.0.0.2:2379"]).unwrap();
+        assert_eq!(target.address, "ipv4:127.0.0.1:2379,127.0.0.2:2379");
+        assert_eq!(target.secure, Some(false));
+
+        let target = resolve(&["https://etcd-0:2379"]).unwrap();
+        assert_eq!(target.address, "dns:///etcd-0:2379");
+        assert_eq!(target.secure, Some(true));
+        assert!(target.host_names.is_empty());
+
+        let target = resolve(&["unix:///var/run/etcd.sock"]).unwrap();
+        assert_eq!(target.address, "unix:/var/run/etcd.sock");
+
+        let target = resolve(&["localhost:2379", "127.0.0.2:2379"]).unwrap();
+        assert!(target.address.starts_with("ipv4:127.0.0.1:2379,"));
+        assert_eq!(target.host_names, vec!["localhost".to_owned()]);
+
+        assert!(resolve(&[]).is_err());
+        assert!(resolve(&["127.0.0.1:2379", "[::1]:2379"]).is_err());
+        assert!(resolve(&["http://127.0.0.1:2379", "https://127.0.0.2:2379"]).is_err());
+        assert!(resolve(&["unix:///var/run/etcd.sock", "127.0.0.1:2379"]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_secure() {
+        let cases: [(&[&str], Option<bool>); 7] = [
+            (&["127.0.0.1:2379", "127.0.0.2:2379"], None),
+            (&["http://127.0.0.1:2379"], Some(false)),
+            (&["https://127.0.0.1:2379"], Some(true)),
+            (&["unix:///var/run/etcd.sock"], Some(false)),
+            (&["unixs:///var/run/etcd.sock"], Some(true)),
+            // An endpoint without a scheme follows the others
+            (&["127.0.0.1:2379", "https://127.0.0.2:2379"], Some(true)),
+            (&["http://127.0.0.1:2379", "127.0.0.2:2379"], Some(false)),
+        ];
+        for (endpoints, secure) in cases {
+            assert_eq!(
+                resolve(endpoints).unwrap().secure,
+                secure,
+                "Fail to detect whether {:?} are secure",
+                endpoints
+            );
+        }
+        assert!(
+            resolve(&["https://127.0.0.1:2379", "http://127.0.0.2:2379"]).is_err(),
+            "Secure and insecure endpoints should not be mixed"
+        );
+    }
+
+    #[test]
+    fn test_resolve_multiple_hosts() {
+        let target = resolve(&["https://localhost:2379", "https://localhost:22379"]).unwrap();
+        assert_eq!(target.secure, Some(true));
+        assert!(
+            target.address.starts_with("ipv4:127.0.0.1:2379,"),
+            "Every host should be resolved, the target is {}",
+            target.address
+        );
+        assert!(target.address.contains("127.0.0.1:22379"));
+        assert_eq!(target.host_names, vec!["localhost".to_owned()]);
+
+        let target = resolve(&["localhost:2379", "127.0.0.1:22379", "localhost:32379"]).unwrap();
+        assert!(target.address.contains("127.0.0.1:32379"));
+        assert_eq!(target.host_names, vec!["localhost".to_owned()]);
+
+        assert!(resolve(&["localhost:2379", "etcd.invalid:2379"]).is_err());
+    }
+}
@@ -0,0 +1,456 @@
+/// Endpoint mod for parsing etcd endpoints.
+mod endpoint;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use grpcio::{
+    Channel, ChannelBuilder, ChannelCredentials, ChannelCredentialsBuilder, EnvBuilder, LbPolicy,
+};
+
+use crate::{
+    auth::AuthToken,
+    protos::{
+        lock_grpc::LockClient,
+        rpc_grpc::{AuthClient, KvClient, LeaseClient, WatchClient},
+    },
+    watch::SingleWatchEventReceiver,
+};
+use endpoint::Target;
+
+use crate::{
+    Auth, CacheCapacity, EtcdError, EvictionPolicyKind, KeyRange, Kv, Lease, Lock, Namespace,
+    Result, RetryPolicy, Watch,
+};
+
+/// Config for establishing etcd client.
+#[non_exhaustive]
+pub struct ClientConfig {
+    /// Etcd server end points, such as `127.0.0.1:2379`, `http://etcd-0:2379`,
+    /// `https://etcd-0:2379` or `unix:///var/run/etcd.sock`. A unix domain socket must be
+    /// the only endpoint. A single host name is resolved again by grpc when the addresses
+    /// change, while several host names are resolved when connecting.
+    pub endpoints: Vec<String>,
+    /// Etcd Auth configurations (User ID, password).
+    pub auth: Option<(String, String)>,
+    /// Etcd client cache capacity, in the number of keys or in bytes.
+    pub cache_capacity: CacheCapacity,
+    /// Etcd client cache eviction policy.
+    pub eviction_policy: EvictionPolicyKind,
+    /// Enable etcd client cache.
+    pub cache_enable: bool,
+    /// Etcd TLS configurations, the connection is insecure if it is `None`.
+    pub tls: Option<TlsConfig>,
+    /// Timeout to wait for the connection to be ready,
+    /// `connect` doesn't wait for the connection if it is `None`.
+    pub connect_timeout: Option<Duration>,
+    /// Retry policy of RPCs.
+    pub retry_policy: RetryPolicy,
+}
+
+impl ClientConfig {
+    /// New a client config
+    #[must_use]
+    #[inline]
+    pub fn new(
+        endpoints: Vec<String>,
+        auth: Option<(String, String)>,
+        cache_size: usize,
+        cache_enable: bool,
+    ) -> Self {
+        Self {
+            endpoints,
+            auth,
+            cache_capacity: CacheCapacity::Entries(cache_size),
+            eviction_policy: EvictionPolicyKind::default(),
+            cache_enable,
+            tls: None,
+            connect_timeout: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Connect to etcd with TLS
+    #[must_use]
+    #[inline]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Wait for the connection to be ready within `timeout` when connecting
+    #[must_use]
+    #[inline]
+    pub const fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the retry policy of RPCs
+    #[must_use]
+    #[inline]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the capacity of the client cache
+    #[must_use]
+    #[inline]
+    pub const fn with_cache_capacity(mut self, cache_capacity: CacheCapacity) -> Self {
+        self.cache_capacity = cache_capacity;
+        self
+    }
+
+    /// Set the eviction policy of the client cache, `EvictionPolicyKind::WTinyLfu` can't be
+    /// used with a capacity in bytes
+    #[must_use]
+    #[inline]
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicyKind) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+}
+
+/// TLS config for establishing a secure connection to etcd.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM encoded CA certificates bundle to verify the etcd server,
+    /// the system default root certificates are used if it is `None`.
+    pub ca_cert: Option<Vec<u8>>,
+    /// PEM encoded client certificate and private key for mutual TLS.
+    pub client_identity: Option<(Vec<u8>, Vec<u8>)>,
+    /// Override the server name used to verify the etcd server certificate.
+    pub server_name_override: Option<String>,
+}
+
+impl TlsConfig {
+    /// New a TLS config using the system default root certificates
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the PEM encoded CA certificates bundle
+    #[must_use]
+    #[inline]
+    pub fn with_ca_cert<C>(mut self, ca_cert: C) -> Self
+    where
+        C: Into<Vec<u8>>,
+    {
+        self.ca_cert = Some(ca_cert.into());
+        self
+    }
+
+    /// Set the PEM encoded client certificate and private key for mutual TLS
+    #[must_use]
+    #[inline]
+    pub fn with_client_identity<C, K>(mut self, cert: C, key: K) -> Self
+    where
+        C: Into<Vec<u8>>,
+        K: Into<Vec<u8>>,
+    {
+        self.client_identity = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Set the server name used to verify the etcd server certificate
+    #[must_use]
+    #[inline]
+    pub fn with_server_name_override<N>(mut self, server_name: N) -> Self
+    where
+        N: Into<String>,
+    {
+        self.server_name_override = Some(server_name.into());
+        self
+    }
+
+    /// Build grpc channel credentials
+    fn credentials(&self) -> ChannelCredentials {
+        let mut builder = ChannelCredentialsBuilder::new();
+        if let Some(ref ca_cert) = self.ca_cert {
+            builder = builder.root_cert(ca_cert.clone());
+        }
+        if let Some((ref cert, ref key)) = self.client_identity {
+            builder = builder.cert(cert.clone(), key.clone());
+        }
+        builder.build()
+    }
+}
+
+/// Client is an abstraction for grouping etcd operations and managing underlying network communications.
+#[derive(Clone)]
+pub struct Client {
+    /// Inner struct for etcd client.
+    inner: Arc<Inner>,
+}
+
+#[allow(dead_code)]
+/// Inner struct
+pub struct Inner {
+    /// A grpc channel used to communicate with Etcd server.
+    channel: Channel,
+    /// Auth client for authentication operations.
+    auth_client: Auth,
+    /// Key-Value client for key-value operations.
+    kv_client: Arc<Kv>,
+    /// Watch client for watch operations.
+    watch_client: Watch,
+    /// Lease client for lease operations.
+    lease_client: Lease,
+    /// Lock client for lock operations.
+    lock_client: Lock,
+}
+
+impl Client {
+    /// Get grpc channel.
+    /// The endpoint schemes decide whether to use TLS, the TLS config is used for the
+    /// endpoints without a scheme.
+    /// With TLS, the server certificates are verified against the host name resolved by the
+    /// client, so several distinct host names need `TlsConfig::server_name_override`.
+    async fn get_channel(cfg: &ClientConfig) -> Result<Channel> {
+        let target = Target::resolve(&cfg.endpoints).await?;
+        let tls = Self::channel_tls(target.secure, cfg.tls.as_ref())?;
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let builder = ChannelBuilder::new(env).load_balancing_policy(LbPolicy::RoundRobin);
+        match tls {
+            Some(tls) => {
+                let builder = match Self::server_name(&tls, &target.host_names)? {
+                    Some(server_name) => builder.override_ssl_target(server_name),
+                    None => builder,
+                };
+                Ok(builder.secure_connect(target.address.as_str(), tls.credentials()))
+            }
+            None => Ok(builder.connect(target.address.as_str())),
+        }
+    }
+
+    /// Decides the TLS config of the channel, `None` for an insecure channel.
+    /// The `https://` endpoints use the system default root certificates if no TLS config
+    /// is set, while the `http://` endpoints refuse a TLS config.
+    fn channel_tls(secure: Option<bool>, tls: Option<&TlsConfig>) -> Result<Option<TlsConfig>> {
+        match (secure, tls) {
+            (Some(false), Some(_)) => Err(EtcdError::InvalidEndpoint(
+                "insecure endpoints can't be used with TLS config".to_owned(),
+            )),
+            (Some(false) | None, None) => Ok(None),
+            (Some(true), None) => Ok(Some(TlsConfig::default())),
+            (Some(true) | None, Some(tls)) => Ok(Some(tls.clone())),
+        }
+    }
+
+    /// Gets the server name to verify the server certificates against, `None` to verify
+    /// against the host of the grpc target.
+    fn server_name<'a>(tls: &'a TlsConfig, host_names: &'a [String]) -> Result<Option<&'a str>> {
+        match (tls.server_name_override.as_ref(), host_names) {
+            (Some(server_name), _) | (None, [server_name]) => Ok(Some(server_name.as_str())),
+            (None, []) => Ok(None),
+            (None, _) => Err(EtcdError::InvalidEndpoint(format!(
+                "the certificates of distinct host names {} can't be verified, \
+                 set the server name override of TLS config",
+                host_names.join(",")
+            ))),
+        }
+    }
+
+    /// Connects to etcd cluster and returns a client.
+    ///
+    /// # Errors
+    /// Will returns `Err` if the config is invalid, failed to contact with given endpoints
+    /// within the connect timeout or authentication failed.
+    #[inline]
+    pub async fn connect(cfg: ClientConfig) -> Result<Self> {
+        // The segments of W-TinyLFU are sized in keys, which are unknown for a capacity in bytes
+        if cfg.cache_enable
+            && matches!(cfg.eviction_policy, EvictionPolicyKind::WTinyLfu)
+            && matches!(cfg.cache_capacity, CacheCapacity::Bytes(_))
+        {
+            return Err(EtcdError::InvalidConfig(
+                "WTinyLfu eviction policy can't be used with a cache capacity in bytes".to_owned(),
+            ));
+        }
+        let channel = Self::get_channel(&cfg).await?;
+        if let Some(timeout) = cfg.connect_timeout {
+            if !channel.wait_for_connected(timeout).await {
+                return Err(EtcdError::ConnectTimeout(timeout));
+            }
+        }
+        let token = Arc::new(AuthToken::new(
+            AuthClient::new(channel.clone()),
+            cfg.auth.clone(),
+        ));
+        token.authenticate().await?;
+        let retry_policy = Arc::new(cfg.retry_policy);
+        let etcd_watch_client = WatchClient::new(channel.clone());
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                channel: channel.clone(),
+                auth_client: Auth::new(
+                    AuthClient::new(channel.clone()),
+                    Arc::clone(&token),
+                    Arc::clone(&retry_policy),
+                ),
+                watch_client: Watch::new(&etcd_watch_client, &token),
+                kv_client: Kv::new(
+                    KvClient::new(channel.clone()),
+                    etcd_watch_client,
+                    Arc::clone(&token),
+                    Arc::clone(&retry_policy),
+                    cfg.cache_capacity,
+                    cfg.eviction_policy,
+                    cfg.cache_enable,
+                ),
+                lease_client: Lease::new(
+                    LeaseClient::new(channel.clone()),
+                    Arc::clone(&token),
+                    retry_policy,
+                ),
+                lock_client: Lock::new(LockClient::new(channel), token),
+            }),
+        })
+    }
+
+    /// Gets an auth client.
+    #[inline]
+    #[must_use]
+    pub fn auth(&self) -> Auth {
+        self.inner.auth_client.clone()
+    }
+
+    /// Gets a key-value client.
+    #[inline]
+    #[must_use]
+    pub fn kv(&self) -> Arc<Kv> {
+        Arc::<Kv>::clone(&self.inner.kv_client)
+    }
+
+    /// Get a lock client.
+    #[inline]
+    #[must_use]
+    pub fn lock(&self) -> Lock {
+        self.inner.lock_client.clone()
+    }
+
+    /// Gets a watch client.
+    #[inline]
+    #[must_use]
+    pub fn watch_client(&self) -> Watch {
+        self.inner.watch_client.clone()
+    }
+
+    /// Perform a watch operation
+    ///
+    ///  # Errors
+    ///
+    /// etcd error: client closed
+    #[inline]
+    pub async fn watch(&self, key_range: KeyRange) -> Result<SingleWatchEventReceiver> {
+        let mut client = self.inner.watch_client.clone();
+        client.watch(key_range).await
+    }
+
+    /// Gets a lease client.
+    #[inline]
+    #[must_use]
+    pub fn lease(&self) -> Lease {
+        self.inner.lease_client.clone()
+    }
+
+    /// Gets a namespaced view of the client, which keeps all the keys under `prefix`.
+    #[inline]
+    #[must_use]
+    pub fn with_namespace<P>(&self, prefix: P) -> Namespace
+    where
+        P: Into<Vec<u8>>,
+    {
+        Namespace::new(self.clone(), prefix.into())
+    }
+
+    /// Shut down any running tasks.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn shutdown(&self) -> Result<()> {
+        let mut watch_client = self.inner.watch_client.clone();
+        watch_client.shutdown().await?;
+        let mut lease_client = self.inner.lease_client.clone();
+        lease_client.shutdown().await?;
+        self.inner.kv_client.shutdown().await;
+        Ok(())
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_tls() {
+        let tls = TlsConfig::new().with_server_name_override("etcd");
+        let cases = [
+            // (secure, tls, uses tls, uses the default tls)
+            (None, None, false, false),
+            (None, Some(&tls), true, false),
+            (Some(false), None, false, false),
+            (Some(true), None, true, true),
+            (Some(true), Some(&tls), true, false),
+        ];
+        for (secure, config, uses_tls, uses_default) in cases {
+            let channel_tls = Client::channel_tls(secure, config).unwrap();
+            assert_eq!(
+                channel_tls.is_some(),
+                uses_tls,
+                "Whether to use TLS is wrong for secure {:?}",
+                secure
+            );
+            if let Some(channel_tls) = channel_tls {
+                assert_eq!(
+                    channel_tls.server_name_override.is_none(),
+                    uses_default,
+                    "The TLS config is wrong for secure {:?}",
+                    secure
+                );
+            }
+        }
+        assert!(
+            matches!(
+                Client::channel_tls(Some(false), Some(&tls)),
+                Err(EtcdError::InvalidEndpoint(_))
+            ),
+            "The http endpoints should refuse TLS config"
+        );
+    }
+
+    #[test]
+    fn test_server_name() {
+        let tls = TlsConfig::new();
+        let overridden = TlsConfig::new().with_server_name_override("etcd");
+        let hosts = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| (*name).to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(Client::server_name(&tls, &[]).unwrap(), None);
+        assert_eq!(
+            Client::server_name(&tls, &hosts(&["etcd-0"])).unwrap(),
+            Some("etcd-0")
+        );
+        assert!(
+            Client::server_name(&tls, &hosts(&["etcd-0", "etcd-1"])).is_err(),
+            "Distinct host names need the server name override"
+        );
+        assert_eq!(
+            Client::server_name(&overridden, &hosts(&["etcd-0", "etcd-1"])).unwrap(),
+            Some("etcd")
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::kv::LocalWatchRequest;
 use crate::lease::EtcdLeaseKeepAliveRequest;
+use grpcio::{RpcStatus, RpcStatusCode};
 use smol::channel::SendError;
+use std::time::Duration;
 
 #[non_exhaustive]
 #[derive(thiserror::Error, Debug)]
@@ -9,9 +11,9 @@ pub enum EtcdError {
     /// InvalidURI
     #[error("invalid URI: {0}")]
     InvalidUri(#[from] http::uri::InvalidUri),
-    /// Transport
+    /// Transport, the gRPC errors which are not recognized as etcd errors
     #[error("gRPC transport error: {0}")]
-    Transport(#[from] grpcio::Error),
+    Transport(grpcio::Error),
     /// SendError for ()
     #[error("send error for (): {0}")]
     SendFailed(#[from] SendError<()>),
@@ -30,4 +32,215 @@ pub enum EtcdError {
     /// Client closed
     #[error("etcd client closed: {0}")]
     ClientClosed(String),
+    /// Invalid endpoint
+    #[error("invalid endpoint: {0}")]
+    InvalidEndpoint(String),
+    /// The client config is invalid, such as conflicting options
+    #[error("invalid client config: {0}")]
+    InvalidConfig(String),
+    /// Connection is not ready within the connect timeout
+    #[error("failed to connect to etcd within {0:?}")]
+    ConnectTimeout(Duration),
+    /// The deadline of the request is exceeded
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+    /// The requested revision has been compacted. The compacted revision is only carried
+    /// for a watch, whose response tells it, while the status of a unary RPC doesn't
+    #[error("required revision has been compacted, the compact revision is {0:?}")]
+    Compacted(Option<i64>),
+    /// The requested revision is greater than the current revision
+    #[error("required revision is a future revision")]
+    FutureRevision,
+    /// The lease is not found
+    #[error("requested lease not found")]
+    LeaseNotFound,
+    /// The etcd cluster has no leader
+    #[error("etcd cluster has no leader")]
+    NoLeader,
+    /// The transaction has too many operations
+    #[error("too many operations in txn request")]
+    TooManyTxnOps,
+    /// The txn built by the client has more operations than the max operations of a txn,
+    /// it is detected before the txn is sent
+    #[error("txn has {0} operations, more than the max {1}")]
+    TxnOpsExceeded(usize, usize),
+    /// The operations of a txn built by the client are on overlapping keys, which etcd
+    /// rejects as duplicate keys
+    #[error("operations on overlapping keys can't be applied in a txn: {0}")]
+    OverlappingTxnOps(String),
+    /// The request is too large
+    #[error("request is too large: {0}")]
+    RequestTooLarge(String),
+    /// The auth token is invalid or expired
+    #[error("invalid auth token")]
+    InvalidAuthToken,
+    /// The user name or password is wrong
+    #[error("authentication failed, invalid user ID or password")]
+    AuthFailed,
+    /// The user has no permission for the request
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    /// The key is not found, such as updating a missing key with `ignore_value`
+    #[error("key not found")]
+    KeyNotFound,
+    /// The optimistic transaction keeps conflicting with other writes
+    #[error("transaction conflicted with other writes after {0} attempts")]
+    TxnConflict(usize),
+    /// IO error of reading or writing a dump
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The dump is malformed or corrupted
+    #[error("invalid dump: {0}")]
+    InvalidDump(String),
+}
+
+impl EtcdError {
+    /// Returns `true` if the error is transient and the request can be retried,
+    /// such as the etcd server is unavailable or the auth token is expired.
+    #[must_use]
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Self::Transport(grpcio::Error::RpcFailure(ref status)) => [
+                RpcStatusCode::UNAVAILABLE,
+                RpcStatusCode::RESOURCE_EXHAUSTED,
+                RpcStatusCode::ABORTED,
+                RpcStatusCode::UNAUTHENTICATED,
+            ]
+            .contains(&status.code()),
+            Self::Transport(grpcio::Error::RemoteStopped)
+            | Self::NoLeader
+            | Self::InvalidAuthToken => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the requested revision has been compacted.
+    #[must_use]
+    #[inline]
+    pub const fn is_compacted(&self) -> bool {
+        matches!(*self, Self::Compacted(_))
+    }
+
+    /// Returns the compacted revision if the error is caused by compaction of a watch,
+    /// it is always `None` for a unary RPC such as a range at a compacted revision.
+    #[must_use]
+    #[inline]
+    pub const fn compact_revision(&self) -> Option<i64> {
+        match *self {
+            Self::Compacted(revision) => revision,
+            _ => None,
+        }
+    }
+
+    /// Maps the gRPC status returned by etcd server to the error.
+    /// The messages are defined in `etcdserver/api/v3rpc/rpctypes/error.go` of etcd.
+    fn from_status(status: &RpcStatus) -> Option<Self> {
+        let message = status.message();
+        let err = match message.strip_prefix("etcdserver: ").unwrap_or(message) {
+            // The message is fixed, it doesn't tell the compacted revision
+            "mvcc: required revision has been compacted" => Self::Compacted(None),
+            "mvcc: required revision is a future revision" => Self::FutureRevision,
+            "requested lease not found" => Self::LeaseNotFound,
+            "no leader" => Self::NoLeader,
+            "too many operations in txn request" => Self::TooManyTxnOps,
+            "request is too large" => Self::RequestTooLarge(message.to_owned()),
+            "invalid auth token" => Self::InvalidAuthToken,
+            "authentication failed, invalid user ID or password" => Self::AuthFailed,
+            "key not found" => Self::KeyNotFound,
+            _ => match status.code() {
+                RpcStatusCode::PERMISSION_DENIED => Self::PermissionDenied(message.to_owned()),
+                RpcStatusCode::DEADLINE_EXCEEDED => Self::DeadlineExceeded,
+                // The message is sent by gRPC when the request exceeds the max message size
+                RpcStatusCode::RESOURCE_EXHAUSTED if message.contains("larger than max") => {
+                    Self::RequestTooLarge(message.to_owned())
+                }
+                _ => return None,
+            },
+        };
+        Some(err)
+    }
+}
+
+impl From<grpcio::Error> for EtcdError {
+    #[inline]
+    fn from(e: grpcio::Error) -> Self {
+        if let grpcio::Error::RpcFailure(ref status) = e {
+            if let Some(err) = Self::from_status(status) {
+                return err;
+            }
+        }
+        Self::Transport(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(code: RpcStatusCode, message: &str) -> EtcdError {
+        grpcio::Error::RpcFailure(RpcStatus::with_message(code, message.to_owned())).into()
+    }
+
+    #[test]
+    fn test_unary_compacted() {
+        // The status of a unary RPC, such as a range at a compacted revision
+        let err = rpc_error(
+            RpcStatusCode::OUT_OF_RANGE,
+            "etcdserver: mvcc: required revision has been compacted",
+        );
+        assert!(err.is_compacted(), "The error should be compacted");
+        assert_eq!(
+            err.compact_revision(),
+            None,
+            "A unary RPC doesn't tell the compacted revision"
+        );
+        assert!(!err.is_retryable());
+
+        let err = EtcdError::Compacted(Some(5));
+        assert!(err.is_compacted());
+        assert_eq!(err.compact_revision(), Some(5));
+    }
+
+    #[test]
+    fn test_error_mapping() {
+        assert!(matches!(
+            rpc_error(
+                RpcStatusCode::NOT_FOUND,
+                "etcdserver: requested lease not found"
+            ),
+            EtcdError::LeaseNotFound
+        ));
+        assert!(matches!(
+            rpc_error(
+                RpcStatusCode::INVALID_ARGUMENT,
+                "etcdserver: request is too large"
+            ),
+            EtcdError::RequestTooLarge(_)
+        ));
+        assert!(matches!(
+            rpc_error(
+                RpcStatusCode::PERMISSION_DENIED,
+                "etcdserver: permission denied"
+            ),
+            EtcdError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            rpc_error(RpcStatusCode::DEADLINE_EXCEEDED, "Deadline Exceeded"),
+            EtcdError::DeadlineExceeded
+        ));
+        assert!(rpc_error(RpcStatusCode::UNAVAILABLE, "etcdserver: no leader").is_retryable());
+        assert!(rpc_error(
+            RpcStatusCode::UNAUTHENTICATED,
+            "etcdserver: invalid auth token"
+        )
+        .is_retryable());
+
+        let err = rpc_error(RpcStatusCode::UNAVAILABLE, "connection refused");
+        assert!(matches!(err, EtcdError::Transport(_)));
+        assert!(err.is_retryable());
+        assert!(
+            !rpc_error(RpcStatusCode::INVALID_ARGUMENT, "etcdserver: key not found").is_retryable()
+        );
+    }
 }
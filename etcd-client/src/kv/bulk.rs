@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use super::KeyRange;
+use crate::{CallOptions, EtcdError, Result};
+
+/// The default max number of operations in a txn, which is the default `--max-txn-ops` of etcd.
+pub(crate) const DEFAULT_MAX_TXN_OPS: usize = 128;
+/// The default max size of a txn request, which leaves room below the default
+/// `--max-request-bytes` (1.5 MiB) of etcd.
+const DEFAULT_MAX_REQUEST_BYTES: usize = 1024 * 1024;
+/// The default number of txns sent concurrently.
+const DEFAULT_CONCURRENCY: usize = 4;
+/// The estimated encoded size of wrapping an operation into a txn.
+const TXN_OP_OVERHEAD: usize = 8;
+
+/// Options of the bulk operations `Kv::put_many` and `Kv::delete_many`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BulkOptions {
+    /// The max number of operations in a txn, should not exceed `--max-txn-ops` of the server.
+    pub max_txn_ops: usize,
+    /// The max encoded size of a txn, should not exceed `--max-request-bytes` of the server.
+    pub max_request_bytes: usize,
+    /// The max number of txns sent concurrently.
+    pub concurrency: usize,
+    /// Refuse to split the operations into several txns, so that they are applied atomically.
+    pub atomic: bool,
+    /// The per-request options of each txn.
+    pub call_options: CallOptions,
+}
+
+impl Default for BulkOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_txn_ops: DEFAULT_MAX_TXN_OPS,
+            max_request_bytes: DEFAULT_MAX_REQUEST_BYTES,
+            concurrency: DEFAULT_CONCURRENCY,
+            atomic: false,
+            call_options: CallOptions::default(),
+        }
+    }
+}
+
+impl BulkOptions {
+    /// Creates a new `BulkOptions` with the default limits of etcd.
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the max number of operations in a txn.
+    #[must_use]
+    #[inline]
+    pub const fn with_max_txn_ops(mut self, max_txn_ops: usize) -> Self {
+        self.max_txn_ops = max_txn_ops;
+        self
+    }
+
+    /// Sets the max encoded size of a txn.
+    #[must_use]
+    #[inline]
+    pub const fn with_max_request_bytes(mut self, max_request_bytes: usize) -> Self {
+        self.max_request_bytes = max_request_bytes;
+        self
+    }
+
+    /// Sets the max number of txns sent concurrently.
+    #[must_use]
+    #[inline]
+    pub const fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Sets whether to apply the operations atomically in a single txn.
+    #[must_use]
+    #[inline]
+    pub const fn with_atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Sets the per-request options of each txn.
+    #[must_use]
+    #[inline]
+    pub fn with_call_options(mut self, call_options: CallOptions) -> Self {
+        self.call_options = call_options;
+        self
+    }
+
+    /// Splits the operations, each with its key range and encoded size, into chunks within
+    /// the limits, returns the index ranges of the chunks.
+    ///
+    /// An operation larger than `max_request_bytes` is put in a chunk alone,
+    /// and left to the server to reject. An operation on the keys overlapping with an
+    /// earlier operation of the chunk starts a new chunk, as etcd rejects a txn with
+    /// duplicate keys, it fails the atomic mode instead.
+    pub(super) fn split(&self, ops: &[(KeyRange, usize)]) -> Result<Vec<Range<usize>>> {
+        let max_txn_ops = self.max_txn_ops.max(1);
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut bytes = 0_usize;
+        for (index, &(ref key_range, size)) in ops.iter().enumerate() {
+            let size = size.saturating_add(TXN_OP_OVERHEAD);
+            let overlapped = ops.get(start..index).map_or(false, |chunk| {
+                chunk.iter().any(|op| op.0.overlaps(key_range))
+            });
+            if overlapped && self.atomic {
+                return Err(EtcdError::OverlappingTxnOps(format!("{}", key_range)));
+            }
+            let full = index.saturating_sub(start) >= max_txn_ops
+                || bytes.saturating_add(size) > self.max_request_bytes
+                || overlapped;
+            if index > start && full {
+                chunks.push(start..index);
+                start = index;
+                bytes = 0;
+            }
+            bytes = bytes.saturating_add(size);
+        }
+        if start < ops.len() {
+            chunks.push(start..ops.len());
+        }
+
+        if self.atomic && chunks.len() > 1 {
+            return Err(if ops.len() > max_txn_ops {
+                EtcdError::TooManyTxnOps
+            } else {
+                EtcdError::RequestTooLarge(format!(
+                    "the operations exceed {} bytes and can't be applied atomically",
+                    self.max_request_bytes
+                ))
+            });
+        }
+        Ok(chunks)
+    }
+}
+
+/// Groups the chunks of the operations into stages in order, where no chunk overlaps with
+/// another chunk of the same stage. The chunks of a stage are applied concurrently, while
+/// a stage is applied after the earlier stages, so the later operation on a key wins.
+pub(super) fn stages(
+    ops: &[(KeyRange, usize)],
+    chunks: Vec<Range<usize>>,
+) -> Vec<Vec<Range<usize>>> {
+    let mut stages = Vec::new();
+    let mut stage = Vec::new();
+    // The single keys and the key ranges operated by the current stage
+    let mut keys = HashSet::<&[u8]>::new();
+    let mut ranges = Vec::<&KeyRange>::new();
+    for chunk in chunks {
+        let chunk_ops = ops.get(chunk.clone()).unwrap_or_default();
+        let overlapped = chunk_ops.iter().any(|&(ref key_range, _)| {
+            let overlaps_key = if key_range.range_end.is_empty() {
+                keys.contains(key_range.key.as_slice())
+            } else {
+                keys.iter().any(|key| key_range.contains(key))
+            };
+            overlaps_key || ranges.iter().any(|range| range.overlaps(key_range))
+        });
+        if overlapped {
+            stages.push(std::mem::take(&mut stage));
+            keys.clear();
+            ranges.clear();
+        }
+        for &(ref key_range, _) in chunk_ops {
+            if key_range.range_end.is_empty() {
+                let _inserted = keys.insert(key_range.key.as_slice());
+            } else {
+                ranges.push(key_range);
+            }
+        }
+        stage.push(chunk);
+    }
+    if !stage.is_empty() {
+        stages.push(stage);
+    }
+    stages
+}
+
+/// The result of a chunk of a bulk operation, which is applied in a single txn.
+#[derive(Debug)]
+pub struct BulkChunk {
+    /// The indexes of the operations in the chunk.
+    ops: Range<usize>,
+    /// The revision of the txn, or the error if the txn is failed.
+    result: Result<usize>,
+}
+
+impl BulkChunk {
+    /// Creates a new `BulkChunk`.
+    pub(super) const fn new(ops: Range<usize>, result: Result<usize>) -> Self {
+        Self { ops, result }
+    }
+
+    /// Gets the indexes of the operations in the chunk, in the order they are passed in.
+    #[inline]
+    #[must_use]
+    pub fn ops(&self) -> Range<usize> {
+        self.ops.clone()
+    }
+
+    /// Gets the revision the chunk is applied at, `None` if the chunk is failed.
+    #[inline]
+    #[must_use]
+    pub fn revision(&self) -> Option<usize> {
+        self.result.as_ref().ok().copied()
+    }
+
+    /// Gets the error of the chunk, `None` if the chunk is applied.
+    #[inline]
+    #[must_use]
+    pub fn error(&self) -> Option<&EtcdError> {
+        self.result.as_ref().err()
+    }
+}
+
+/// Response of the bulk operations `Kv::put_many` and `Kv::delete_many`.
+#[derive(Debug)]
+pub struct BulkResponse {
+    /// The results of the chunks, in the order of the operations.
+    chunks: Vec<BulkChunk>,
+}
+
+impl BulkResponse {
+    /// Creates a new `BulkResponse`.
+    pub(super) const fn new(chunks: Vec<BulkChunk>) -> Self {
+        Self { chunks }
+    }
+
+    /// Gets the results of the chunks, in the order of the operations.
+    #[inline]
+    #[must_use]
+    pub fn chunks(&self) -> &[BulkChunk] {
+        &self.chunks
+    }
+
+    /// Returns `true` if all the chunks are applied.
+    #[inline]
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.chunks.iter().all(|chunk| chunk.result.is_ok())
+    }
+
+    /// Gets the chunks which are failed.
+    #[inline]
+    pub fn failures(&self) -> impl Iterator<Item = &BulkChunk> {
+        self.chunks.iter().filter(|chunk| chunk.result.is_err())
+    }
+
+    /// Gets the latest revision of the applied chunks, `None` if no chunk is applied.
+    #[inline]
+    #[must_use]
+    pub fn revision(&self) -> Option<usize> {
+        self.chunks.iter().filter_map(BulkChunk::revision).max()
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the operations on distinct keys of the encoded sizes.
+    fn ops(sizes: &[usize]) -> Vec<(KeyRange, usize)> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| (KeyRange::key(format!("key{}", i)), size))
+            .collect()
+    }
+
+    #[test]
+    fn test_bulk_split() {
+        let options = BulkOptions::new()
+            .with_max_txn_ops(3)
+            .with_max_request_bytes(100);
+
+        assert_eq!(
+            options.split(&ops(&[1; 7])).unwrap(),
+            vec![0..3, 3..6, 6..7],
+            "Chunks should not exceed the max txn ops"
+        );
+        assert_eq!(
+            options.split(&ops(&[40, 40, 40, 200, 10])).unwrap(),
+            vec![0..2, 2..3, 3..4, 4..5],
+            "Chunks should not exceed the max request bytes"
+        );
+        assert!(
+            options.split(&[]).unwrap().is_empty(),
+            "No operations should have no chunks"
+        );
+        assert!(
+            matches!(
+                options.clone().with_atomic(true).split(&ops(&[1; 4])),
+                Err(EtcdError::TooManyTxnOps)
+            ),
+            "Atomic mode should refuse to chunk"
+        );
+        assert!(
+            matches!(
+                options.clone().with_atomic(true).split(&ops(&[60, 60])),
+                Err(EtcdError::RequestTooLarge(_))
+            ),
+            "Atomic mode should refuse to chunk"
+        );
+
+        let overlapping = vec![
+            (KeyRange::key("a"), 1),
+            (KeyRange::prefix("b"), 1),
+            (KeyRange::key("b1"), 1),
+            (KeyRange::key("c"), 1),
+            (KeyRange::key("c"), 1),
+        ];
+        assert_eq!(
+            options.split(&overlapping).unwrap(),
+            vec![0..2, 2..4, 4..5],
+            "Operations on overlapping keys should be in different chunks"
+        );
+        assert!(
+            matches!(
+                options.with_atomic(true).split(&overlapping),
+                Err(EtcdError::OverlappingTxnOps(_))
+            ),
+            "Atomic mode should refuse operations on overlapping keys"
+        );
+    }
+
+    #[test]
+    fn test_bulk_stages() {
+        let options = BulkOptions::new().with_max_txn_ops(2);
+
+        let distinct = ops(&[1; 5]);
+        assert_eq!(
+            stages(&distinct, options.split(&distinct).unwrap()),
+            vec![vec![0..2, 2..4, 4..5]],
+            "Chunks on distinct keys should be applied concurrently"
+        );
+
+        let overlapping = vec![
+            (KeyRange::key("a"), 1),
+            (KeyRange::key("b"), 1),
+            (KeyRange::key("c"), 1),
+            (KeyRange::key("a"), 1),
+            (KeyRange::prefix("b"), 1),
+            (KeyRange::key("d"), 1),
+            (KeyRange::key("e"), 1),
+            (KeyRange::key("b1"), 1),
+        ];
+        let chunks = options.split(&overlapping).unwrap();
+        assert_eq!(chunks, vec![0..2, 2..4, 4..6, 6..8]);
+        assert_eq!(
+            stages(&overlapping, chunks),
+            vec![vec![0..2], vec![2..4, 4..6], vec![6..8]],
+            "A chunk overlapping an earlier chunk should wait for it"
+        );
+    }
+}
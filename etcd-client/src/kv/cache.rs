@@ -2,66 +2,91 @@
 use super::KeyValue;
 use super::LocalWatchRequest;
 use super::OverflowArithmetic;
+use super::{CacheCapacity, EvictionPolicy};
 use crate::Result as Res;
 use lockfree_cuckoohash::{pin, LockFreeCuckooHash};
-use priority_queue::PriorityQueue;
 use smol::channel::Sender;
-use smol::lock::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 /// Cache entry
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct CacheEntry {
     /// current revision of key in cache
     revision: i64,
-    /// key value, None means key has been deleted
-    /// but watch is not cancelled yet.
+    /// key value, None means key is absent at the revision,
+    /// it is kept valid by the watch of the key as well.
     kv: Option<KeyValue>,
+    /// The charge of the entry against the cache capacity.
+    charge: usize,
 }
 
 impl CacheEntry {
     /// Create a new `CacheEntry`.
-    pub const fn new(kv: Option<KeyValue>, revision: i64) -> Self {
-        Self { revision, kv }
+    pub const fn new(kv: Option<KeyValue>, revision: i64, charge: usize) -> Self {
+        Self {
+            revision,
+            kv,
+            charge,
+        }
     }
 }
+
+/// The result of a cache hit.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CacheHit {
+    /// The key-value pair of the key.
+    Present(KeyValue),
+    /// The key doesn't exist.
+    Absent,
+}
+
 /// Cache struct contains a lock-free hashTable.
+///
+/// The keys to evict are picked by the `EvictionPolicy`, which is told of every insert,
+/// hit and removal. The search path only records the hit in the policy without a lock.
 pub struct Cache {
     /// map to store key value
     hashtable: LockFreeCuckooHash<Vec<u8>, CacheEntry>,
-    /// lru queue of the keys in hashtable.
-    lru_queue: Mutex<PriorityQueue<Vec<u8>, u64>>,
+    /// The policy picking the keys to evict.
+    policy: Box<dyn EvictionPolicy>,
+    /// The capacity of the cache.
+    capacity: CacheCapacity,
+    /// The total charge of the entries in hashtable.
+    charge: AtomicUsize,
+    /// Whether a task is evicting keys, so only one task does it at a time.
+    evicting: AtomicBool,
 }
 
 impl Cache {
-    /// Create a new `Cache` with specified size.
-    pub fn new(size: usize) -> Self {
+    /// Create a new `Cache` with specified capacity and eviction policy,
+    /// the hashtable is allocated for `size` keys.
+    #[inline]
+    #[must_use]
+    pub fn new(capacity: CacheCapacity, policy: Box<dyn EvictionPolicy>, size: usize) -> Self {
         Self {
             hashtable: LockFreeCuckooHash::with_capacity(size),
-            lru_queue: Mutex::new(PriorityQueue::new()),
+            policy,
+            capacity,
+            charge: AtomicUsize::new(0),
+            evicting: AtomicBool::new(false),
         }
     }
 
-    /// Searches a `key` from the cache.
-    pub async fn search(&self, key: &[u8]) -> Option<KeyValue> {
-        let search_result = {
-            let guard = pin();
-            self.hashtable.get(key, &guard).cloned()
-        };
-        match search_result {
-            Some(entry) => {
-                if let Some(kv) = entry.kv {
-                    self.lru_queue
-                        .lock()
-                        .await
-                        .change_priority(key, Self::get_priority());
-                    Some(kv)
-                } else {
-                    None
-                }
-            }
-            None => None,
-        }
+    /// Searches a `key` from the cache, the key may be cached as absent.
+    #[inline]
+    #[must_use]
+    pub fn search(&self, key: &[u8]) -> Option<CacheHit> {
+        let guard = pin();
+        let entry = self.hashtable.get(key, &guard)?;
+        self.policy.on_hit(key);
+        Some(entry.kv.clone().map_or(CacheHit::Absent, CacheHit::Present))
+    }
+
+    /// Gets the revision of a cached `key`, the mod revision if it is present,
+    /// or the revision it is known absent at.
+    pub(crate) fn revision(&self, key: &[u8]) -> Option<i64> {
+        let guard = pin();
+        self.hashtable.get(key, &guard).map(|entry| entry.revision)
     }
 
     /// Check if the new `CacheEntry` has higher revision.
@@ -72,79 +97,212 @@ impl Cache {
     /// Helper function to insert or update a `key` to the cache.
     /// Return `(bool, bool)` first bool indicates if operation succeed
     /// second bool indicates if it is an insert or not.
-    async fn insert_or_update_helper(
+    fn insert_or_update_helper(
         &self,
         key: Vec<u8>,
-        value: KeyValue,
-        mark_delete: bool,
+        kv: Option<KeyValue>,
+        revision: i64,
     ) -> (bool, bool) {
-        let revision = value.get_mod_revision();
-        let res = {
+        let value = match kv {
+            Some(ref kv) => kv.get_value(),
+            None => &[],
+        };
+        let charge = self.capacity.charge(&key, value);
+        let (succeed, old_charge) = {
             let guard = &pin();
             let (succeed, old_value) = self.hashtable.insert_or_update_on(
                 key.clone(),
-                CacheEntry::new(if mark_delete { None } else { Some(value) }, revision),
+                CacheEntry::new(kv, revision, charge),
                 Self::higher_revision,
                 guard,
             );
-            (succeed, old_value.is_none())
+            (succeed, old_value.map(|old| old.charge))
         };
-        if res.0 {
-            self.lru_queue.lock().await.push(key, Self::get_priority());
+        if succeed {
+            self.charge.fetch_add(charge, Ordering::Relaxed);
+            match old_charge {
+                Some(old_charge) => {
+                    self.charge.fetch_sub(old_charge, Ordering::Relaxed);
+                }
+                None => self.policy.on_insert(&key),
+            }
         }
-        res
+        (succeed, old_charge.is_none())
     }
 
     /// Insert or update a `key` to the cache.
-    pub async fn insert_or_update(&self, key: Vec<u8>, value: KeyValue) -> (bool, bool) {
-        self.insert_or_update_helper(key, value, false).await
+    pub(crate) fn insert_or_update(&self, key: Vec<u8>, value: KeyValue) -> (bool, bool) {
+        let revision = value.get_mod_revision();
+        self.insert_or_update_helper(key, Some(value), revision)
     }
 
     /// Remove a `key` from cache totally
-    pub async fn remove(&self, key: &[u8]) {
-        // Adopt the following sequence to keep consistency between hashtable and lru_queue
-        //
-        // | Sequence         | Sequence         | Sequence         | Sequence         | Sequence         | Sequence         |
-        // | insert hashtable | insert hashtable | remove lru_queue | insert hashtable | remove lru_queue | remove lru_queue |
-        // | insert lru_queue | remove lru_queue | insert hashtable | remove lru_queue | insert hashtable | remove hashtable |
-        // | remove lru_queue | insert lru_queue | insert lru_queue | remove hashtable | remove hashtable | insert hashtable |
-        // | remove hashtable | remove hashtable | remove hashtable | insert lru_queue | insert lru_queue | insert lru_queue |
-        // | Good             | <----------  key in lru_queue not in hashtable, but it is OK  -------->   | Good             |
-        self.lru_queue.lock().await.remove(key);
-        self.hashtable.remove(key);
+    pub(crate) fn remove(&self, key: &[u8]) {
+        if self.remove_entry(key).is_some() {
+            self.policy.on_remove(key);
+        }
     }
 
-    /// Mark a `key` as delete.
-    pub async fn mark_delete(&self, key: Vec<u8>, value: KeyValue) {
-        self.insert_or_update_helper(key, value, true).await;
+    /// Removes the entry of a `key` from hashtable and releases its charge, returns the
+    /// charge of the entry if it is cached. The policy is not told.
+    ///
+    /// The charge is read from the removed entry itself, as the entry may be replaced
+    /// concurrently, whose charge is adjusted by the replacing task.
+    fn remove_entry(&self, key: &[u8]) -> Option<usize> {
+        let charge = {
+            let guard = pin();
+            self.hashtable.remove_with_guard(key, &guard)?.charge
+        };
+        self.charge.fetch_sub(charge, Ordering::Relaxed);
+        Some(charge)
     }
 
-    /// Gets the priority of a key in lru queue.
-    fn get_priority() -> u64 {
-        let current_time = SystemTime::now();
-        let since_the_epoch = current_time
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_else(|e| panic!("Fail to get the time since the epoch, the error is {}", e));
-
-        u64::MAX.overflow_sub(since_the_epoch.as_secs())
+    /// Mark a `key` as absent at `revision`, such as it is deleted at the revision or not
+    /// found by a read at the revision, returns the same as `insert_or_update`.
+    #[inline]
+    #[must_use]
+    pub fn mark_absent(&self, key: Vec<u8>, revision: i64) -> (bool, bool) {
+        self.insert_or_update_helper(key, None, revision)
     }
 
-    /// Adjusts cache size if the number of value in cache has exceed the threshold(0.8 * capacity).
+    /// Adjusts cache size if the charge of the cache has exceed the threshold(0.8 * capacity).
     /// Adjusts cache to 0.6 * capacity
-    pub async fn adjust_cache_size(&self, watch_sender: &Sender<LocalWatchRequest>) -> Res<()> {
-        if let Some(mut queue) = self.lru_queue.try_lock() {
-            let upper_bound = self.hashtable.capacity().overflow_mul(8).overflow_div(10);
-            let lower_bound = self.hashtable.capacity().overflow_mul(6).overflow_div(10);
-
-            if queue.len() > upper_bound {
-                while queue.len() > lower_bound {
-                    if let Some(pop_value) = queue.pop() {
-                        let key = pop_value.0;
-                        watch_sender.send(LocalWatchRequest::cancel(key)).await?;
-                    }
-                }
+    pub(crate) async fn adjust_cache_size(
+        &self,
+        watch_sender: &Sender<LocalWatchRequest>,
+    ) -> Res<()> {
+        if self
+            .evicting
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Ok(());
+        }
+        let res = self.evict(watch_sender).await;
+        self.evicting.store(false, Ordering::Release);
+        res
+    }
+
+    /// Evicts the keys picked by the policy. A victim is removed from hashtable as soon as
+    /// it is picked, as the policy forgets it, then its watch is cancelled.
+    async fn evict(&self, watch_sender: &Sender<LocalWatchRequest>) -> Res<()> {
+        let limit = self.capacity.limit();
+        let upper_bound = limit.overflow_mul(8).overflow_div(10);
+        let lower_bound = limit.overflow_mul(6).overflow_div(10);
+        let charge = self.charge.load(Ordering::Relaxed);
+
+        if charge > upper_bound {
+            let mut to_evict = charge.overflow_sub(lower_bound);
+            while to_evict > 0 {
+                let key = match self.policy.victim() {
+                    Some(key) => key,
+                    None => break,
+                };
+                let victim_charge = match self.remove_entry(&key) {
+                    Some(charge) => charge,
+                    None => continue,
+                };
+                watch_sender.send(LocalWatchRequest::cancel(key)).await?;
+                to_evict = to_evict.saturating_sub(victim_charge);
             }
         }
         Ok(())
     }
 }
+
+#[allow(clippy::indexing_slicing)]
+#[cfg(test)]
+mod tests {
+    use super::super::LruPolicy;
+    use super::*;
+
+    /// Creates a `KeyValue` of the key at the revision.
+    fn key_value(key: &[u8], revision: i64) -> KeyValue {
+        let mut kv = KeyValue::new();
+        kv.set_key(key.to_vec());
+        kv.set_value(b"value".to_vec());
+        kv.set_mod_revision(revision);
+        kv
+    }
+
+    #[test]
+    fn test_byte_capacity_eviction() -> Res<()> {
+        // Each entry is charged 4 bytes of key and 5 bytes of value
+        let cache = Cache::new(CacheCapacity::Bytes(90), Box::new(LruPolicy::new()), 16);
+        let keys = (0..9)
+            .map(|i| format!("key{}", i).into_bytes())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            cache.insert_or_update(key.clone(), key_value(key, 1));
+        }
+        // Hit the first key, and update the second key whose charge is unchanged
+        assert!(
+            cache.search(&keys[0]).is_some(),
+            "The first key should be cached"
+        );
+        cache.insert_or_update(keys[1].clone(), key_value(&keys[1], 2));
+        assert_eq!(
+            cache.charge.load(Ordering::Relaxed),
+            81,
+            "The update should not change the charge"
+        );
+
+        let (sender, receiver) = smol::channel::unbounded();
+        smol::block_on(cache.adjust_cache_size(&sender))?;
+        let mut evicted = Vec::new();
+        while let Ok(req) = receiver.try_recv() {
+            evicted.push(req.key);
+        }
+        assert_eq!(
+            evicted.len(),
+            3,
+            "The cache should be adjusted to 0.6 * capacity in bytes"
+        );
+        assert!(
+            !evicted.contains(&keys[0]),
+            "The referenced key should get a second chance"
+        );
+        assert_eq!(
+            cache.charge.load(Ordering::Relaxed),
+            54,
+            "The charge of the evicted keys should be released before the cancels are applied"
+        );
+        for key in &evicted {
+            assert!(
+                cache.search(key).is_none(),
+                "The evicted key should be removed"
+            );
+            // Applying the cancel removes nothing more
+            cache.remove(key);
+        }
+        assert_eq!(cache.charge.load(Ordering::Relaxed), 54);
+        Ok(())
+    }
+
+    #[test]
+    fn test_absent_key() {
+        let cache = Cache::new(CacheCapacity::Entries(16), Box::new(LruPolicy::new()), 16);
+        assert_eq!(
+            cache.mark_absent(b"key".to_vec(), 2),
+            (true, true),
+            "The absent key should be inserted"
+        );
+        assert_eq!(
+            cache.search(b"key"),
+            Some(CacheHit::Absent),
+            "The absent key should be a hit"
+        );
+        cache.insert_or_update(b"key".to_vec(), key_value(b"key", 1));
+        assert_eq!(
+            cache.search(b"key"),
+            Some(CacheHit::Absent),
+            "The absent key should not be overwritten by an older value"
+        );
+        cache.insert_or_update(b"key".to_vec(), key_value(b"key", 3));
+        assert_eq!(
+            cache.search(b"key"),
+            Some(CacheHit::Present(key_value(b"key", 3))),
+            "The absent key should be replaced by a newer value"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use clippy_utilities::Cast;
+use log::{debug, warn};
+use smol::Timer;
+
+use super::Kv;
+use crate::protos::rpc::{CompactionRequest, CompactionResponse};
+use crate::CallOptions;
+use crate::ResponseHeader;
+
+/// Request for compacting the key-value store history.
+#[derive(Debug, Clone)]
+pub struct EtcdCompactRequest {
+    /// Etcd compaction request.
+    proto: CompactionRequest,
+    /// Per-request options.
+    options: CallOptions,
+}
+
+impl EtcdCompactRequest {
+    /// Creates a new `EtcdCompactRequest` which compacts all the revisions before `revision`.
+    #[inline]
+    #[must_use]
+    pub fn new(revision: usize) -> Self {
+        let proto = CompactionRequest {
+            revision: revision.cast(),
+            physical: false,
+            ..CompactionRequest::default()
+        };
+
+        Self {
+            proto,
+            options: CallOptions::default(),
+        }
+    }
+
+    /// When set, the RPC waits until the compaction is physically applied to the
+    /// local database, so that the compacted entries are totally removed.
+    #[inline]
+    pub fn set_physical(&mut self, physical: bool) {
+        self.proto.physical = physical;
+    }
+
+    /// Gets the revision to compact to.
+    #[inline]
+    pub fn get_revision(&self) -> usize {
+        self.proto.revision.cast()
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
+}
+
+impl From<EtcdCompactRequest> for CompactionRequest {
+    #[inline]
+    fn from(e: EtcdCompactRequest) -> Self {
+        e.proto
+    }
+}
+
+/// Response for compacting the key-value store history.
+#[derive(Debug)]
+pub struct EtcdCompactResponse {
+    /// Etcd compaction response.
+    proto: CompactionResponse,
+}
+
+impl EtcdCompactResponse {
+    /// Takes the header out of response, leaving a `None` in its place.
+    #[inline]
+    pub fn take_header(&mut self) -> Option<ResponseHeader> {
+        self.proto.header.take().map(From::from)
+    }
+}
+
+impl From<CompactionResponse> for EtcdCompactResponse {
+    #[inline]
+    fn from(resp: CompactionResponse) -> Self {
+        Self { proto: resp }
+    }
+}
+
+/// The history retained by the auto compaction.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionRetention {
+    /// Retain the latest revisions of the given number.
+    Revisions(usize),
+    /// Retain the revisions created within the given duration.
+    Period(Duration),
+}
+
+/// Compacts the history periodically according to `retention`, checks every `interval`.
+/// Errors are logged and the compaction is retried in the next round.
+pub(super) async fn run_auto_compaction(
+    kv: &Kv,
+    retention: CompactionRetention,
+    interval: Duration,
+) {
+    // The revisions sampled in each round, used by the period retention
+    let mut samples: VecDeque<(Instant, usize)> = VecDeque::new();
+    let mut compacted = 0;
+    loop {
+        match kv.current_revision().await {
+            Ok(revision) => {
+                let target = match retention {
+                    CompactionRetention::Revisions(count) => revision.saturating_sub(count),
+                    CompactionRetention::Period(period) => {
+                        let now = Instant::now();
+                        samples.push_back((now, revision));
+                        // Compact to the latest revision sampled at least `period` ago
+                        let mut target = 0;
+                        while let Some(&(sampled_at, sampled)) = samples.front() {
+                            if now.saturating_duration_since(sampled_at) < period {
+                                break;
+                            }
+                            target = sampled;
+                            let _sample = samples.pop_front();
+                        }
+                        target
+                    }
+                };
+                if target > compacted {
+                    match kv.compact(EtcdCompactRequest::new(target)).await {
+                        Ok(_) => {
+                            debug!("Auto compaction compacted to revision {}", target);
+                            compacted = target;
+                        }
+                        // Someone else has compacted the history
+                        Err(ref e) if e.is_compacted() => compacted = target,
+                        Err(e) => warn!(
+                            "Auto compaction failed to compact to revision {}, the error is: {}",
+                            target, e
+                        ),
+                    }
+                }
+            }
+            Err(e) => warn!(
+                "Auto compaction failed to get the current revision, the error is: {}",
+                e
+            ),
+        }
+        Timer::after(interval).await;
+    }
+}
@@ -1,13 +1,18 @@
 use super::{EtcdKeyValue, KeyRange};
+use crate::namespace;
 use crate::proto::etcdserverpb::{DeleteRangeRequest, DeleteRangeResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
+use prost::Message;
 
 /// Request for deleting key-value pairs.
 #[derive(Debug, Clone)]
 pub struct EtcdDeleteRequest {
     /// Etcd delete range key-value pairs request.
     proto: DeleteRangeRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdDeleteRequest {
@@ -23,6 +28,7 @@ impl EtcdDeleteRequest {
         };
         Self {
             proto: delete_range_request,
+            options: CallOptions::default(),
         }
     }
 
@@ -38,11 +44,38 @@ impl EtcdDeleteRequest {
         self.proto.key.as_slice()
     }
 
+    /// Gets the key range of the request.
+    pub(super) fn key_range(&self) -> KeyRange {
+        KeyRange::range(self.proto.key.clone(), self.proto.range_end.clone())
+    }
+
+    /// Gets the encoded size of the request.
+    pub(super) fn encoded_len(&self) -> usize {
+        Message::encoded_len(&self.proto)
+    }
+
     /// Wether request previous kv or not
     #[inline]
     pub const fn request_prev_kv(&self) -> bool {
         self.proto.prev_kv
     }
+
+    /// Prepends the namespace prefix to the keys of the request.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        namespace::prefix_key_range(prefix, &mut self.proto.key, &mut self.proto.range_end);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdDeleteRequest> for DeleteRangeRequest {
@@ -105,6 +138,13 @@ impl EtcdDeleteResponse {
     pub fn get_revision(&self) -> i64 {
         self.proto.header.unwrap().revision
     }
+
+    /// Strips the namespace prefix from the keys of the response.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        for kv in &mut self.proto.prev_kvs {
+            namespace::strip_key(prefix, &mut kv.key);
+        }
+    }
 }
 
 impl From<DeleteRangeResponse> for EtcdDeleteResponse {
@@ -0,0 +1,91 @@
+use std::cmp::Ordering;
+
+use futures::TryStreamExt;
+
+use super::{EtcdKeyValue, KeyRange, Kv};
+use crate::Result;
+
+/// The max number of keys in a page of the snapshots compared.
+const DIFF_PAGE_SIZE: usize = 1000;
+
+/// The change of a key between two revisions.
+#[non_exhaustive]
+#[derive(Clone, PartialEq)]
+pub enum KeyChange {
+    /// The key is created, with its key-value pair at the new revision.
+    Added(EtcdKeyValue),
+    /// The key is modified, with its key-value pairs at the old and the new revisions.
+    Modified {
+        /// The key-value pair at the old revision.
+        old: EtcdKeyValue,
+        /// The key-value pair at the new revision.
+        new: EtcdKeyValue,
+    },
+    /// The key is deleted, with its key-value pair at the old revision.
+    Deleted(EtcdKeyValue),
+}
+
+impl KeyChange {
+    /// Gets the key changed.
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &[u8] {
+        match *self {
+            Self::Added(ref kv) | Self::Deleted(ref kv) | Self::Modified { new: ref kv, .. } => {
+                kv.key()
+            }
+        }
+    }
+}
+
+/// Compares the snapshots of `key_range` at `old_revision` and `new_revision`,
+/// returns the changes ordered by key.
+///
+/// Both snapshots are read page by page, and merged as they are both ordered by key.
+pub(super) async fn diff(
+    kv: &Kv,
+    key_range: KeyRange,
+    old_revision: usize,
+    new_revision: usize,
+) -> Result<Vec<KeyChange>> {
+    let mut old_stream = kv.range_stream_at(key_range.clone(), DIFF_PAGE_SIZE, old_revision);
+    let mut new_stream = kv.range_stream_at(key_range, DIFF_PAGE_SIZE, new_revision);
+    let mut old_kv = old_stream.try_next().await?;
+    let mut new_kv = new_stream.try_next().await?;
+
+    let mut changes = Vec::new();
+    loop {
+        match (old_kv.take(), new_kv.take()) {
+            (None, None) => break,
+            (Some(old), None) => {
+                changes.push(KeyChange::Deleted(old));
+                old_kv = old_stream.try_next().await?;
+            }
+            (None, Some(new)) => {
+                changes.push(KeyChange::Added(new));
+                new_kv = new_stream.try_next().await?;
+            }
+            (Some(old), Some(new)) => match old.key().cmp(new.key()) {
+                Ordering::Less => {
+                    changes.push(KeyChange::Deleted(old));
+                    new_kv = Some(new);
+                    old_kv = old_stream.try_next().await?;
+                }
+                Ordering::Greater => {
+                    changes.push(KeyChange::Added(new));
+                    old_kv = Some(old);
+                    new_kv = new_stream.try_next().await?;
+                }
+                Ordering::Equal => {
+                    // A key deleted and created again is also modified
+                    if old.mod_revision() != new.mod_revision() {
+                        changes.push(KeyChange::Modified { old, new });
+                    }
+                    old_kv = old_stream.try_next().await?;
+                    new_kv = new_stream.try_next().await?;
+                }
+            },
+        }
+    }
+    Ok(changes)
+}
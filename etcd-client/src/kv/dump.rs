@@ -0,0 +1,469 @@
+//! Portable dump of a key range.
+//!
+//! A dump is in the JSON Lines format, each line is a JSON object whose `type` field
+//! tells the kind of the record. Keys and values are encoded in standard base64.
+//!
+//! ```text
+//! {"type":"header","format":"etcd-client-dump","version":1,"revision":42,"key":"...","range_end":"..."}
+//! {"type":"entry","key":"...","value":"...","create_revision":7,"mod_revision":9,"version":2,"lease":0}
+//! ...
+//! {"type":"trailer","count":2,"checksum":"5d2f8a3c"}
+//! ```
+//!
+//! - The header is the first line. `revision` is the revision the key range is read at,
+//!   and `key`/`range_end` is the key range dumped.
+//! - The entries are ordered by key, and all read at `revision`.
+//! - The trailer is the last line. `count` is the number of entries, and `checksum` is the
+//!   CRC32 in hex of all the preceding lines, including their line feeds.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use futures::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    BulkOptions, BulkResponse, EtcdKeyValue, EtcdPutRequest, EtcdTxnRequest, KeyRange, Kv, TxnOp,
+};
+use crate::{EtcdError, Result};
+
+/// The format name in the header.
+const DUMP_FORMAT: &str = "etcd-client-dump";
+/// The version of the dump format.
+const DUMP_VERSION: u32 = 1;
+/// The max number of keys in a page read by the export.
+const EXPORT_PAGE_SIZE: usize = 1000;
+/// The estimated encoded size of the compare guarding a put of the skip-existing mode.
+const COMPARE_OVERHEAD: usize = 16;
+
+/// A line of the dump.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DumpRecord {
+    /// The first line, describing the dump.
+    Header {
+        /// The format name, always `etcd-client-dump`.
+        format: String,
+        /// The version of the format.
+        version: u32,
+        /// The revision the key range is read at.
+        revision: usize,
+        /// The start of the key range in base64.
+        key: String,
+        /// The end of the key range in base64.
+        range_end: String,
+    },
+    /// A key-value pair.
+    Entry {
+        /// The key in base64.
+        key: String,
+        /// The value in base64.
+        value: String,
+        /// The revision the key is created at.
+        create_revision: usize,
+        /// The revision the key is last modified at.
+        mod_revision: usize,
+        /// The number of modifications since the key is created.
+        version: usize,
+        /// The lease attached to the key, 0 if none.
+        lease: u64,
+    },
+    /// The last line, to verify the dump is complete.
+    Trailer {
+        /// The number of entries.
+        count: usize,
+        /// The CRC32 in hex of all the preceding lines.
+        checksum: String,
+    },
+}
+
+impl From<&EtcdKeyValue> for DumpRecord {
+    fn from(kv: &EtcdKeyValue) -> Self {
+        Self::Entry {
+            key: BASE64.encode(kv.key()),
+            value: BASE64.encode(kv.value()),
+            create_revision: kv.create_revision(),
+            mod_revision: kv.mod_revision(),
+            version: kv.version(),
+            lease: kv.lease(),
+        }
+    }
+}
+
+/// How the import treats the keys which already exist.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Overwrite the existing keys.
+    Overwrite,
+    /// Keep the existing keys, only the missing keys are imported.
+    SkipExisting,
+}
+
+/// Options of `Kv::import`.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOptions {
+    /// How to treat the keys which already exist.
+    pub mode: ImportMode,
+    /// Rewrite the prefix of the keys from the first one to the second one.
+    pub rewrite_prefix: Option<(Vec<u8>, Vec<u8>)>,
+    /// The options of the txns applying the entries.
+    pub bulk: BulkOptions,
+}
+
+impl Default for ImportOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            mode: ImportMode::Overwrite,
+            rewrite_prefix: None,
+            bulk: BulkOptions::default(),
+        }
+    }
+}
+
+impl ImportOptions {
+    /// Creates a new `ImportOptions` which overwrites the existing keys.
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how to treat the keys which already exist.
+    #[must_use]
+    #[inline]
+    pub const fn with_mode(mut self, mode: ImportMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Rewrites the keys starting with `from` to start with `to` instead.
+    /// The import fails if any key doesn't start with `from`.
+    #[must_use]
+    #[inline]
+    pub fn with_rewrite_prefix<F, T>(mut self, from: F, to: T) -> Self
+    where
+        F: Into<Vec<u8>>,
+        T: Into<Vec<u8>>,
+    {
+        self.rewrite_prefix = Some((from.into(), to.into()));
+        self
+    }
+
+    /// Sets the options of the txns applying the entries.
+    #[must_use]
+    #[inline]
+    pub fn with_bulk_options(mut self, bulk: BulkOptions) -> Self {
+        self.bulk = bulk;
+        self
+    }
+}
+
+/// Writes a record as a line, and feeds the line to the checksum.
+async fn write_record<W>(
+    writer: &mut W,
+    hasher: &mut crc32fast::Hasher,
+    record: &DumpRecord,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut line = serde_json::to_vec(record)
+        .map_err(|e| EtcdError::InternalError(format!("failed to encode dump record: {}", e)))?;
+    line.push(b'\n');
+    hasher.update(&line);
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+/// Decodes a base64 field of a record.
+fn decode(field: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(field)
+        .map_err(|e| EtcdError::InvalidDump(format!("invalid base64 {:?}: {}", field, e)))
+}
+
+/// Writes the dump of `key_range` to `writer`, returns the revision it is read at.
+pub(super) async fn export<W>(kv: &Kv, key_range: KeyRange, writer: &mut W) -> Result<usize>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut stream = kv.range_stream(key_range.clone(), EXPORT_PAGE_SIZE);
+    // The revision is known after the first page is read
+    let mut next = stream.try_next().await?;
+    let revision = stream
+        .revision()
+        .ok_or_else(|| EtcdError::InternalError("range response has no header".to_owned()))?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    let header = DumpRecord::Header {
+        format: DUMP_FORMAT.to_owned(),
+        version: DUMP_VERSION,
+        revision,
+        key: BASE64.encode(&key_range.key),
+        range_end: BASE64.encode(&key_range.range_end),
+    };
+    write_record(writer, &mut hasher, &header).await?;
+    let mut count = 0_usize;
+    while let Some(entry) = next {
+        write_record(writer, &mut hasher, &DumpRecord::from(&entry)).await?;
+        count = count.saturating_add(1);
+        next = stream.try_next().await?;
+    }
+
+    let trailer = DumpRecord::Trailer {
+        count,
+        checksum: format!("{:08x}", hasher.finalize()),
+    };
+    write_record(writer, &mut crc32fast::Hasher::new(), &trailer).await?;
+    writer.flush().await?;
+    Ok(revision)
+}
+
+/// Reads a dump from `reader` and verifies it, returns the key range dumped and the
+/// key-value pairs. Every entry should be in the key range and read at the revision of
+/// the header.
+async fn read_dump<R>(reader: R) -> Result<(KeyRange, Vec<(Vec<u8>, Vec<u8>)>)>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut lines = reader.lines();
+    let mut hasher = crc32fast::Hasher::new();
+    let mut entries = Vec::new();
+    let mut header: Option<(KeyRange, usize)> = None;
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        let record: DumpRecord = serde_json::from_str(&line)
+            .map_err(|e| EtcdError::InvalidDump(format!("malformed line {:?}: {}", line, e)))?;
+        match record {
+            DumpRecord::Header {
+                format,
+                version,
+                revision,
+                key,
+                range_end,
+            } if header.is_none() => {
+                if format != DUMP_FORMAT || version != DUMP_VERSION {
+                    return Err(EtcdError::InvalidDump(format!(
+                        "unsupported format {} version {}",
+                        format, version
+                    )));
+                }
+                header = Some((
+                    KeyRange::range(decode(&key)?, decode(&range_end)?),
+                    revision,
+                ));
+            }
+            DumpRecord::Entry {
+                key,
+                value,
+                mod_revision,
+                ..
+            } => {
+                let &(ref key_range, revision) = header
+                    .as_ref()
+                    .ok_or_else(|| EtcdError::InvalidDump(format!("unexpected line {:?}", line)))?;
+                let key = decode(&key)?;
+                if !key_range.contains(&key) {
+                    return Err(EtcdError::InvalidDump(format!(
+                        "key {:?} is out of the key range {}",
+                        String::from_utf8_lossy(&key),
+                        key_range
+                    )));
+                }
+                if mod_revision > revision {
+                    return Err(EtcdError::InvalidDump(format!(
+                        "key {:?} is modified at revision {} after the dump revision {}",
+                        String::from_utf8_lossy(&key),
+                        mod_revision,
+                        revision
+                    )));
+                }
+                entries.push((key, decode(&value)?));
+            }
+            DumpRecord::Trailer { count, checksum } if header.is_some() => {
+                if count != entries.len() {
+                    return Err(EtcdError::InvalidDump(format!(
+                        "expect {} entries, but found {}",
+                        count,
+                        entries.len()
+                    )));
+                }
+                let actual = format!("{:08x}", hasher.finalize());
+                if checksum != actual {
+                    return Err(EtcdError::InvalidDump(format!(
+                        "checksum mismatch, expect {}, but is {}",
+                        checksum, actual
+                    )));
+                }
+                if let Some((key_range, _)) = header {
+                    return Ok((key_range, entries));
+                }
+            }
+            _ => {
+                return Err(EtcdError::InvalidDump(format!(
+                    "unexpected line {:?}",
+                    line
+                )))
+            }
+        }
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    Err(EtcdError::InvalidDump(
+        "the dump is truncated, no trailer is found".to_owned(),
+    ))
+}
+
+/// Returns `true` if all the keys of the key range start with `prefix`.
+fn within_prefix(key_range: &KeyRange, prefix: &[u8]) -> bool {
+    if !key_range.key.starts_with(prefix) {
+        return false;
+    }
+    let prefix_end = KeyRange::prefix(prefix).range_end;
+    match (key_range.range_end.as_slice(), prefix_end.as_slice()) {
+        ([], _) | (_, [0]) => true,
+        ([0], _) => false,
+        (range_end, prefix_end) => range_end <= prefix_end,
+    }
+}
+
+/// Imports a dump from `reader`. The whole dump is read and verified before any entry
+/// is written, then the entries are written in chunked txns.
+pub(super) async fn import<R>(kv: &Kv, reader: R, options: ImportOptions) -> Result<BulkResponse>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let (key_range, mut entries) = read_dump(reader).await?;
+    if let Some((ref from, ref to)) = options.rewrite_prefix {
+        if !within_prefix(&key_range, from) {
+            return Err(EtcdError::InvalidDump(format!(
+                "the key range {} is not under the prefix {:?} to rewrite",
+                key_range,
+                String::from_utf8_lossy(from)
+            )));
+        }
+        for &mut (ref mut key, _) in &mut entries {
+            let suffix = key.strip_prefix(from.as_slice()).ok_or_else(|| {
+                EtcdError::InvalidDump(format!(
+                    "key {:?} doesn't start with the prefix {:?}",
+                    String::from_utf8_lossy(key),
+                    String::from_utf8_lossy(from)
+                ))
+            })?;
+            let mut rewritten = to.clone();
+            rewritten.extend_from_slice(suffix);
+            *key = rewritten;
+        }
+    }
+
+    let ops = entries
+        .into_iter()
+        .map(|(key, value)| {
+            let put = EtcdPutRequest::new(key.clone(), value);
+            match options.mode {
+                ImportMode::Overwrite => (KeyRange::key(key), put.encoded_len(), TxnOp::from(put)),
+                ImportMode::SkipExisting => {
+                    let size = put
+                        .encoded_len()
+                        .saturating_add(key.len())
+                        .saturating_add(COMPARE_OVERHEAD);
+                    // Each put is guarded by its own nested txn, so that an existing key
+                    // doesn't fail the other puts
+                    let txn = EtcdTxnRequest::new()
+                        .when_not_exists(KeyRange::key(key.clone()))
+                        .and_then(put);
+                    (KeyRange::key(key), size, TxnOp::from(txn))
+                }
+            }
+        })
+        .collect();
+    kv.bulk(ops, options.bulk).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a dump of the key range at `revision` with the entries of key and mod revision.
+    fn dump(key_range: &KeyRange, revision: usize, entries: &[(&str, usize)]) -> Vec<u8> {
+        smol::block_on(async {
+            let mut buf = Vec::new();
+            let mut hasher = crc32fast::Hasher::new();
+            let header = DumpRecord::Header {
+                format: DUMP_FORMAT.to_owned(),
+                version: DUMP_VERSION,
+                revision,
+                key: BASE64.encode(&key_range.key),
+                range_end: BASE64.encode(&key_range.range_end),
+            };
+            write_record(&mut buf, &mut hasher, &header).await?;
+            for &(key, mod_revision) in entries {
+                let entry = DumpRecord::Entry {
+                    key: BASE64.encode(key),
+                    value: BASE64.encode("value"),
+                    create_revision: mod_revision,
+                    mod_revision,
+                    version: 1,
+                    lease: 0,
+                };
+                write_record(&mut buf, &mut hasher, &entry).await?;
+            }
+            let trailer = DumpRecord::Trailer {
+                count: entries.len(),
+                checksum: format!("{:08x}", hasher.finalize()),
+            };
+            write_record(&mut buf, &mut crc32fast::Hasher::new(), &trailer).await?;
+            Ok::<_, EtcdError>(buf)
+        })
+        .unwrap_or_else(|e| panic!("Fail to write dump, the error is {}", e))
+    }
+
+    #[test]
+    fn test_read_dump() {
+        let key_range = KeyRange::prefix("app/");
+        let read = |buf: Vec<u8>| smol::block_on(read_dump(futures::io::Cursor::new(buf)));
+
+        let (read_range, entries) = read(dump(&key_range, 5, &[("app/a", 3), ("app/b", 5)]))
+            .unwrap_or_else(|e| panic!("The dump should be valid, the error is {}", e));
+        assert!(read_range == key_range, "The key range should be read");
+        assert_eq!(entries.len(), 2, "All the entries should be read");
+
+        assert!(
+            matches!(
+                read(dump(&key_range, 5, &[("other/a", 3)])),
+                Err(EtcdError::InvalidDump(_))
+            ),
+            "The key out of the key range should be rejected"
+        );
+        assert!(
+            matches!(
+                read(dump(&key_range, 5, &[("app/a", 6)])),
+                Err(EtcdError::InvalidDump(_))
+            ),
+            "The key modified after the revision should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_within_prefix() {
+        assert!(
+            within_prefix(&KeyRange::prefix("app/a/"), b"app/"),
+            "The key range under the prefix should be rewritable"
+        );
+        assert!(
+            within_prefix(&KeyRange::key("app/a"), b"app/"),
+            "The key under the prefix should be rewritable"
+        );
+        assert!(
+            !within_prefix(&KeyRange::prefix("app"), b"app/"),
+            "The key range wider than the prefix should not be rewritable"
+        );
+        assert!(
+            !within_prefix(&KeyRange::all(), b"app/"),
+            "All the keys should not be rewritable"
+        );
+    }
+}
@@ -0,0 +1,683 @@
+//! The eviction policies of Etcd cache.
+use super::OverflowArithmetic;
+use clippy_utilities::Cast;
+use crossbeam_queue::SegQueue;
+use lockfree_cuckoohash::{pin, LockFreeCuckooHash};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// The number of keys sampled by `LfuPolicy` to pick a victim.
+const LFU_SAMPLE_SIZE: usize = 5;
+/// The number of rows of the frequency sketch of `WTinyLfuPolicy`.
+const SKETCH_DEPTH: usize = 4;
+/// The max count of a counter of the frequency sketch.
+const SKETCH_MAX_COUNT: u8 = 15;
+/// The percentage of the keys kept in the window of `WTinyLfuPolicy`.
+const WINDOW_PERCENTAGE: usize = 1;
+
+/// The capacity of the cache.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheCapacity {
+    /// The max number of cached keys.
+    Entries(usize),
+    /// The max total bytes of the cached keys and values.
+    Bytes(usize),
+}
+
+impl CacheCapacity {
+    /// Gets the limit of the capacity.
+    pub(crate) const fn limit(self) -> usize {
+        match self {
+            Self::Entries(limit) | Self::Bytes(limit) => limit,
+        }
+    }
+
+    /// Gets the charge of a key-value pair against the capacity.
+    pub(crate) fn charge(self, key: &[u8], value: &[u8]) -> usize {
+        match self {
+            Self::Entries(_) => 1,
+            Self::Bytes(_) => key.len().overflow_add(value.len()),
+        }
+    }
+}
+
+/// A policy deciding which keys to evict when the cache is full.
+///
+/// `on_hit` is called on the search path of the cache, so it should be cheap and never
+/// block. A key passed to `on_remove` may be unknown to the policy, such as a victim.
+pub trait EvictionPolicy: Send + Sync {
+    /// Records that a key is inserted into the cache.
+    fn on_insert(&self, key: &[u8]);
+    /// Records a hit of a key.
+    fn on_hit(&self, key: &[u8]);
+    /// Records that a key is removed from the cache.
+    fn on_remove(&self, key: &[u8]);
+    /// Picks a key to evict and forgets it, `None` if the policy knows no key.
+    fn victim(&self) -> Option<Vec<u8>>;
+}
+
+/// The eviction policy of the cache, selected in `ClientConfig`.
+#[non_exhaustive]
+#[derive(Clone)]
+pub enum EvictionPolicyKind {
+    /// [`LruPolicy`]
+    Lru,
+    /// [`LfuPolicy`]
+    Lfu,
+    /// [`WTinyLfuPolicy`], it is sized in keys and can't be used with `CacheCapacity::Bytes`
+    WTinyLfu,
+    /// A custom policy, the function is called with the expected number of cached keys
+    /// each time the cache is created.
+    Custom(Arc<dyn Fn(usize) -> Box<dyn EvictionPolicy> + Send + Sync>),
+}
+
+impl Default for EvictionPolicyKind {
+    #[inline]
+    fn default() -> Self {
+        Self::Lru
+    }
+}
+
+impl fmt::Debug for EvictionPolicyKind {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Lru => write!(f, "Lru"),
+            Self::Lfu => write!(f, "Lfu"),
+            Self::WTinyLfu => write!(f, "WTinyLfu"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl EvictionPolicyKind {
+    /// Builds the policy for a cache expected to hold `entries` keys.
+    pub(crate) fn build(&self, entries: usize) -> Box<dyn EvictionPolicy> {
+        match *self {
+            Self::Lru => Box::new(LruPolicy::new()),
+            Self::Lfu => Box::new(LfuPolicy::new()),
+            Self::WTinyLfu => Box::new(WTinyLfuPolicy::new(entries)),
+            Self::Custom(ref build) => build(entries),
+        }
+    }
+}
+
+/// A queue of keys in the CLOCK order, each key has a reference bit set when it is hit.
+/// An item of the queue is stale if the key is removed or inserted again since it is
+/// queued, and is dropped when popped.
+struct Clock {
+    /// The reference bits of the keys.
+    bits: LockFreeCuckooHash<Vec<u8>, Arc<AtomicBool>>,
+    /// The keys in the order of insertion, with their reference bits.
+    queue: SegQueue<(Vec<u8>, Arc<AtomicBool>)>,
+    /// The number of keys.
+    len: AtomicUsize,
+}
+
+impl Clock {
+    /// Creates an empty `Clock`.
+    fn new() -> Self {
+        Self {
+            bits: LockFreeCuckooHash::new(),
+            queue: SegQueue::new(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the number of keys.
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Inserts a key at the tail.
+    fn insert(&self, key: &[u8]) {
+        let bit = Arc::new(AtomicBool::new(false));
+        let is_insert = {
+            let guard = &pin();
+            let (_, old_bit) =
+                self.bits
+                    .insert_or_update_on(key.to_vec(), Arc::clone(&bit), |_, _| true, guard);
+            old_bit.is_none()
+        };
+        if is_insert {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        self.queue.push((key.to_vec(), bit));
+        if self.queue.len() > self.len().overflow_mul(2) {
+            self.drop_stale();
+        }
+    }
+
+    /// Sets the reference bit of a key, returns `false` if the key is unknown.
+    fn hit(&self, key: &[u8]) -> bool {
+        let guard = pin();
+        self.bits.get(key, &guard).map_or(false, |bit| {
+            bit.store(true, Ordering::Relaxed);
+            true
+        })
+    }
+
+    /// Removes a key, returns `false` if the key is unknown.
+    fn remove(&self, key: &[u8]) -> bool {
+        let removed = self.bits.remove(key);
+        if removed {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Returns `true` if the queue item is of the current key.
+    fn is_live(&self, key: &[u8], bit: &Arc<AtomicBool>) -> bool {
+        let guard = pin();
+        self.bits
+            .get(key, &guard)
+            .map_or(false, |current| Arc::ptr_eq(current, bit))
+    }
+
+    /// Pops the next key in the CLOCK order without removing it, the keys whose
+    /// reference bits are set get a second chance.
+    fn pop(&self) -> Option<(Vec<u8>, Arc<AtomicBool>)> {
+        loop {
+            let (key, bit) = self.queue.pop()?;
+            if !self.is_live(&key, &bit) {
+                continue;
+            }
+            if bit.swap(false, Ordering::Relaxed) {
+                self.queue.push((key, bit));
+            } else {
+                return Some((key, bit));
+            }
+        }
+    }
+
+    /// Pops and removes the next key in the CLOCK order.
+    fn victim(&self) -> Option<Vec<u8>> {
+        let (key, _) = self.pop()?;
+        self.remove(&key);
+        Some(key)
+    }
+
+    /// Drops the stale items piled up by the keys inserted again, keeping the order of others.
+    fn drop_stale(&self) {
+        for _ in 0..self.queue.len() {
+            match self.queue.pop() {
+                Some((key, bit)) if self.is_live(&key, &bit) => self.queue.push((key, bit)),
+                Some(_) => {}
+                None => break,
+            }
+        }
+    }
+}
+
+/// Least recently used policy, approximated by CLOCK: a hit only sets the reference bit
+/// of the key without taking a lock, and a key whose bit is set gets a second chance.
+pub struct LruPolicy {
+    /// The keys in the CLOCK order.
+    clock: Clock,
+}
+
+impl LruPolicy {
+    /// Creates a new `LruPolicy`.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            clock: Clock::new(),
+        }
+    }
+}
+
+impl Default for LruPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvictionPolicy for LruPolicy {
+    #[inline]
+    fn on_insert(&self, key: &[u8]) {
+        self.clock.insert(key);
+    }
+
+    #[inline]
+    fn on_hit(&self, key: &[u8]) {
+        self.clock.hit(key);
+    }
+
+    #[inline]
+    fn on_remove(&self, key: &[u8]) {
+        self.clock.remove(key);
+    }
+
+    #[inline]
+    fn victim(&self) -> Option<Vec<u8>> {
+        self.clock.victim()
+    }
+}
+
+/// The keys `LfuPolicy` samples from, with their hit counts. An entry is stale if the key
+/// is removed or inserted again since it is pushed, and is dropped when it is sampled or
+/// when the stale entries pile up.
+struct SamplePool {
+    /// The keys with their hit counts.
+    keys: Vec<(Vec<u8>, Arc<AtomicU64>)>,
+    /// The state of the xorshift random number generator, never 0.
+    seed: u64,
+}
+
+impl SamplePool {
+    /// Generates the next random number.
+    fn next_random(&mut self) -> u64 {
+        self.seed ^= self.seed << 13_i32;
+        self.seed ^= self.seed >> 7_i32;
+        self.seed ^= self.seed << 17_i32;
+        self.seed
+    }
+}
+
+/// Least frequently used policy. The victim is the least hit key of a few randomly sampled
+/// keys, and the hit counts of the other sampled keys are halved so that the old hits fade
+/// out. A hit only increases the count of the key without taking a lock.
+pub struct LfuPolicy {
+    /// The hit counts of the keys.
+    counts: LockFreeCuckooHash<Vec<u8>, Arc<AtomicU64>>,
+    /// The number of keys.
+    len: AtomicUsize,
+    /// The keys to sample from.
+    pool: Mutex<SamplePool>,
+}
+
+impl LfuPolicy {
+    /// Creates a new `LfuPolicy`.
+    #[inline]
+    #[must_use]
+    pub fn new() -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Self {
+            counts: LockFreeCuckooHash::new(),
+            len: AtomicUsize::new(0),
+            pool: Mutex::new(SamplePool {
+                keys: Vec::new(),
+                seed: seed.max(1),
+            }),
+        }
+    }
+
+    /// Locks the pool of the keys.
+    fn pool(&self) -> MutexGuard<'_, SamplePool> {
+        self.pool.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns `true` if the pool entry is of the current key.
+    fn is_live(&self, entry: &(Vec<u8>, Arc<AtomicU64>)) -> bool {
+        let guard = pin();
+        self.counts
+            .get(&entry.0, &guard)
+            .map_or(false, |current| Arc::ptr_eq(current, &entry.1))
+    }
+
+    /// Removes a key, returns `false` if the key is unknown.
+    fn remove(&self, key: &[u8]) -> bool {
+        let removed = self.counts.remove(key);
+        if removed {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+}
+
+impl Default for LfuPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvictionPolicy for LfuPolicy {
+    #[inline]
+    fn on_insert(&self, key: &[u8]) {
+        let count = Arc::new(AtomicU64::new(0));
+        let is_insert = {
+            let guard = &pin();
+            let (_, old_count) = self.counts.insert_or_update_on(
+                key.to_vec(),
+                Arc::clone(&count),
+                |_, _| true,
+                guard,
+            );
+            old_count.is_none()
+        };
+        if is_insert {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut pool = self.pool();
+        pool.keys.push((key.to_vec(), count));
+        // Drop the stale entries piled up by the keys removed or inserted again
+        if pool.keys.len() > self.len.load(Ordering::Relaxed).overflow_mul(2) {
+            pool.keys.retain(|entry| self.is_live(entry));
+        }
+    }
+
+    #[inline]
+    fn on_hit(&self, key: &[u8]) {
+        let guard = pin();
+        if let Some(count) = self.counts.get(key, &guard) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    fn on_remove(&self, key: &[u8]) {
+        self.remove(key);
+    }
+
+    #[inline]
+    fn victim(&self) -> Option<Vec<u8>> {
+        let mut pool = self.pool();
+        // Move the random samples to the front by a partial Fisher-Yates shuffle,
+        // the stale entries met are dropped
+        let mut sampled = 0_usize;
+        while sampled < LFU_SAMPLE_SIZE && sampled < pool.keys.len() {
+            let remaining: u64 = pool.keys.len().overflow_sub(sampled).cast();
+            let offset: usize = pool.next_random().overflow_rem(remaining).cast();
+            pool.keys.swap(sampled, sampled.overflow_add(offset));
+            if pool
+                .keys
+                .get(sampled)
+                .map_or(false, |entry| self.is_live(entry))
+            {
+                sampled = sampled.overflow_add(1);
+            } else {
+                let _stale = pool.keys.swap_remove(sampled);
+            }
+        }
+
+        let samples = pool.keys.get(..sampled)?;
+        let (index, _) = samples
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &(_, ref count))| count.load(Ordering::Relaxed))?;
+        for &(_, ref count) in samples {
+            let halved = count.load(Ordering::Relaxed).overflow_div(2);
+            count.store(halved, Ordering::Relaxed);
+        }
+        let (key, _) = pool.keys.swap_remove(index);
+        drop(pool);
+        self.remove(&key);
+        Some(key)
+    }
+}
+
+/// A count-min sketch estimating the access frequencies of the keys with 4-bit counters,
+/// the counters are halved after a number of increments so the estimation follows the
+/// recent accesses.
+struct FrequencySketch {
+    /// The counters of all the rows.
+    counters: Vec<AtomicU8>,
+    /// The number of counters in a row, a power of two.
+    width: usize,
+    /// The number of increments since the last reset.
+    additions: AtomicUsize,
+    /// The number of increments to reset the counters after.
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    /// Creates a sketch for about `entries` keys.
+    fn new(entries: usize) -> Self {
+        let width = entries.max(16).next_power_of_two();
+        Self {
+            counters: (0..width.overflow_mul(SKETCH_DEPTH))
+                .map(|_| AtomicU8::new(0))
+                .collect(),
+            width,
+            additions: AtomicUsize::new(0),
+            sample_size: width.overflow_mul(10),
+        }
+    }
+
+    /// Gets the counters of a key, one in each row.
+    fn counters(&self, key: &[u8]) -> impl Iterator<Item = &AtomicU8> {
+        (0..SKETCH_DEPTH).filter_map(move |row| {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            key.hash(&mut hasher);
+            let column: usize = hasher.finish().cast();
+            let index = row
+                .overflow_mul(self.width)
+                .overflow_add(column & self.width.overflow_sub(1));
+            self.counters.get(index)
+        })
+    }
+
+    /// Estimates the frequency of a key.
+    fn frequency(&self, key: &[u8]) -> u8 {
+        self.counters(key)
+            .map(|counter| counter.load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Records an access of a key.
+    fn increment(&self, key: &[u8]) {
+        for counter in self.counters(key) {
+            let _saturated = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count < SKETCH_MAX_COUNT).then(|| count.overflow_add(1))
+            });
+        }
+        if self.additions.fetch_add(1, Ordering::Relaxed) >= self.sample_size {
+            self.additions.store(0, Ordering::Relaxed);
+            for counter in &self.counters {
+                let count = counter.load(Ordering::Relaxed);
+                counter.store(count.overflow_div(2), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Window TinyLFU policy. A new key enters a small LRU window, and is moved to the
+/// probation after it leaves the window. When the cache is full, a key leaving the
+/// probation is admitted to the LRU main space only if it is accessed more frequently than
+/// the LRU victim of the main space by the frequency sketch, otherwise it is evicted. It
+/// keeps the frequently accessed keys from being flushed by a burst of keys accessed once.
+pub struct WTinyLfuPolicy {
+    /// The window of the new keys.
+    window: Clock,
+    /// The keys left the window and not yet admitted to the main space.
+    probation: Clock,
+    /// The main space of the admitted keys.
+    main: Clock,
+    /// The max number of keys in the window.
+    window_capacity: usize,
+    /// The max number of keys in the main space.
+    main_capacity: usize,
+    /// The access frequencies of the keys, including the ones not cached.
+    sketch: FrequencySketch,
+}
+
+impl WTinyLfuPolicy {
+    /// Creates a new `WTinyLfuPolicy` for a cache expected to hold `entries` keys.
+    #[inline]
+    #[must_use]
+    pub fn new(entries: usize) -> Self {
+        let window_capacity = entries
+            .overflow_mul(WINDOW_PERCENTAGE)
+            .overflow_div(100)
+            .max(1);
+        Self {
+            window: Clock::new(),
+            probation: Clock::new(),
+            main: Clock::new(),
+            window_capacity,
+            main_capacity: entries.saturating_sub(window_capacity).max(1),
+            sketch: FrequencySketch::new(entries),
+        }
+    }
+}
+
+impl EvictionPolicy for WTinyLfuPolicy {
+    #[inline]
+    fn on_insert(&self, key: &[u8]) {
+        self.sketch.increment(key);
+        self.window.insert(key);
+        while self.window.len() > self.window_capacity {
+            match self.window.victim() {
+                Some(key) => self.probation.insert(&key),
+                None => break,
+            }
+        }
+    }
+
+    #[inline]
+    fn on_hit(&self, key: &[u8]) {
+        self.sketch.increment(key);
+        let _hit = self.window.hit(key) || self.probation.hit(key) || self.main.hit(key);
+    }
+
+    #[inline]
+    fn on_remove(&self, key: &[u8]) {
+        let _removed =
+            self.window.remove(key) || self.probation.remove(key) || self.main.remove(key);
+    }
+
+    #[inline]
+    fn victim(&self) -> Option<Vec<u8>> {
+        loop {
+            let candidate = match self.probation.victim().or_else(|| self.window.victim()) {
+                Some(candidate) => candidate,
+                None => return self.main.victim(),
+            };
+            if self.main.len() < self.main_capacity {
+                self.main.insert(&candidate);
+                continue;
+            }
+            let victim = match self.main.victim() {
+                Some(victim) => victim,
+                None => return Some(candidate),
+            };
+            return if self.sketch.frequency(&candidate) > self.sketch.frequency(&victim) {
+                self.main.insert(&candidate);
+                Some(victim)
+            } else {
+                self.main.insert(&victim);
+                Some(candidate)
+            };
+        }
+    }
+}
+
+#[allow(clippy::indexing_slicing)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inserts the keys `key0`, `key1` ... into the policy.
+    fn insert_keys(policy: &dyn EvictionPolicy, n: usize) -> Vec<Vec<u8>> {
+        let keys = (0..n)
+            .map(|i| format!("key{}", i).into_bytes())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            policy.on_insert(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn test_lru_policy() {
+        let policy = LruPolicy::new();
+        let keys = insert_keys(&policy, 4);
+        policy.on_hit(&keys[0]);
+        // The removed and inserted again key is at the tail
+        policy.on_remove(&keys[1]);
+        policy.on_insert(&keys[1]);
+
+        let victims = (0..4).filter_map(|_| policy.victim()).collect::<Vec<_>>();
+        assert_eq!(
+            victims,
+            vec![
+                keys[2].clone(),
+                keys[3].clone(),
+                keys[1].clone(),
+                keys[0].clone()
+            ],
+            "The hit key should get a second chance"
+        );
+        assert_eq!(policy.victim(), None, "All the keys should be evicted");
+    }
+
+    #[test]
+    fn test_lfu_policy() {
+        let policy = LfuPolicy::new();
+        let keys = insert_keys(&policy, LFU_SAMPLE_SIZE);
+        for (i, key) in keys.iter().enumerate() {
+            for _ in 0..=i {
+                policy.on_hit(key);
+            }
+        }
+        policy.on_hit(&keys[0]);
+        policy.on_hit(&keys[0]);
+        assert_eq!(
+            policy.victim(),
+            Some(keys[1].clone()),
+            "The least frequently hit key should be evicted"
+        );
+    }
+
+    #[test]
+    fn test_lfu_policy_stale_keys() {
+        let policy = LfuPolicy::new();
+        let keys = insert_keys(&policy, 2);
+        for _ in 0..10 {
+            policy.on_remove(&keys[0]);
+            policy.on_insert(&keys[0]);
+        }
+        assert!(
+            policy.pool().keys.len() <= 4,
+            "The stale entries should be purged"
+        );
+
+        policy.on_remove(&keys[1]);
+        assert_eq!(policy.victim(), Some(keys[0].clone()));
+        assert_eq!(policy.victim(), None, "All the keys should be evicted");
+        assert!(policy.pool().keys.is_empty());
+    }
+
+    #[test]
+    fn test_w_tiny_lfu_policy() {
+        let policy = WTinyLfuPolicy::new(4);
+        let hot = insert_keys(&policy, 4);
+        // Three keys are admitted to the main space, and the last one is rejected
+        assert_eq!(
+            policy.victim(),
+            Some(hot[3].clone()),
+            "The key not more frequent than the main victim should be evicted"
+        );
+        for _ in 0..10 {
+            for key in &hot[..3] {
+                policy.on_hit(key);
+            }
+        }
+
+        // A burst of keys accessed only once should not flush the hot keys
+        let cold = (0..5)
+            .map(|i| format!("cold{}", i).into_bytes())
+            .collect::<Vec<_>>();
+        for key in &cold {
+            policy.on_insert(key);
+        }
+        for _ in 0..cold.len() {
+            let victim = policy
+                .victim()
+                .unwrap_or_else(|| panic!("A victim should be picked"));
+            assert!(
+                cold.contains(&victim),
+                "The cold key should be evicted instead of the hot keys"
+            );
+        }
+    }
+}
@@ -1,13 +1,18 @@
-use super::EtcdKeyValue;
-use crate::proto::etcdserverpb::{RangeRequest, RangeResponse};
+use super::{EtcdKeyValue, SortOrder, SortTarget};
+use crate::namespace;
+use crate::proto::etcdserverpb::{range_request, RangeRequest, RangeResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
+use std::collections::HashMap;
 
 /// Request for fetching a single key-value pair.
 #[derive(Debug, Clone)]
 pub struct EtcdGetRequest {
     /// Etcd range fetching request.
     proto: RangeRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdGetRequest {
@@ -36,6 +41,7 @@ impl EtcdGetRequest {
         };
         Self {
             proto: range_request,
+            options: CallOptions::default(),
         }
     }
 
@@ -46,11 +52,107 @@ impl EtcdGetRequest {
         self.proto.limit = limit.cast();
     }
 
+    /// Sets the revision of the point-in-time key-value store to read.
+    /// When revision is set to 0, the latest revision is read.
+    #[inline]
+    pub fn set_revision(&mut self, revision: usize) {
+        self.proto.revision = revision.cast();
+    }
+
+    /// Sets the order of the returned keys.
+    #[inline]
+    pub fn set_sort_order(&mut self, sort_order: SortOrder) {
+        self.proto.sort_order = range_request::SortOrder::from(sort_order).into();
+    }
+
+    /// Sets the field to sort the returned keys by.
+    #[inline]
+    pub fn set_sort_target(&mut self, sort_target: SortTarget) {
+        self.proto.sort_target = range_request::SortTarget::from(sort_target).into();
+    }
+
+    /// When set, the request is served by the local member without going through consensus,
+    /// which is faster but may return stale data.
+    #[inline]
+    pub fn set_serializable(&mut self, serializable: bool) {
+        self.proto.serializable = serializable;
+    }
+
+    /// When set, only the keys are returned without values.
+    #[inline]
+    pub fn set_keys_only(&mut self, keys_only: bool) {
+        self.proto.keys_only = keys_only;
+    }
+
+    /// When set, only the count of the keys is returned.
+    #[inline]
+    pub fn set_count_only(&mut self, count_only: bool) {
+        self.proto.count_only = count_only;
+    }
+
+    /// Filters out the keys whose mod revision is less than `revision`.
+    #[inline]
+    pub fn set_min_mod_revision(&mut self, revision: usize) {
+        self.proto.min_mod_revision = revision.cast();
+    }
+
+    /// Filters out the keys whose mod revision is greater than `revision`.
+    #[inline]
+    pub fn set_max_mod_revision(&mut self, revision: usize) {
+        self.proto.max_mod_revision = revision.cast();
+    }
+
+    /// Filters out the keys whose create revision is less than `revision`.
+    #[inline]
+    pub fn set_min_create_revision(&mut self, revision: usize) {
+        self.proto.min_create_revision = revision.cast();
+    }
+
+    /// Filters out the keys whose create revision is greater than `revision`.
+    #[inline]
+    pub fn set_max_create_revision(&mut self, revision: usize) {
+        self.proto.max_create_revision = revision.cast();
+    }
+
     /// Gets the `key_range` from the `RangeRequest`.
     #[inline]
     pub fn get_key(&self) -> &[u8] {
         self.proto.key.as_slice()
     }
+
+    /// Creates a `EtcdGetRequest` from a built `RangeRequest`.
+    pub(super) const fn from_parts(proto: RangeRequest, options: CallOptions) -> Self {
+        Self { proto, options }
+    }
+
+    /// Returns `true` if the request reads the latest value of the key,
+    /// which can be served by the cache.
+    pub(super) const fn is_cacheable(&self) -> bool {
+        self.proto.revision == 0
+            && !self.proto.keys_only
+            && !self.proto.count_only
+            && self.proto.min_mod_revision == 0
+            && self.proto.max_mod_revision == 0
+            && self.proto.min_create_revision == 0
+            && self.proto.max_create_revision == 0
+    }
+
+    /// Prepends the namespace prefix to the keys of the request.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        namespace::prefix_key(prefix, &mut self.proto.key);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdGetRequest> for RangeRequest {
@@ -106,6 +208,13 @@ impl EtcdGetResponse {
         self.proto.kvs.clone().into_iter().map(From::from).collect()
     }
 
+    /// Strips the namespace prefix from the keys of the response.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        for kv in &mut self.proto.kvs {
+            namespace::strip_key(prefix, &mut kv.key);
+        }
+    }
+
     /// Consume `EtcdGetRequest` and return inner `RangeResponse`
     #[allow(clippy::missing_const_for_fn)] // false alarm
     #[inline]
@@ -120,3 +229,38 @@ impl From<RangeResponse> for EtcdGetResponse {
         Self { proto: resp }
     }
 }
+
+/// Response for fetching several keys at a single revision.
+pub struct EtcdGetManyResponse {
+    /// The key-value pairs of the keys which exist.
+    kvs: HashMap<Vec<u8>, EtcdKeyValue>,
+    /// The revision the keys are read at.
+    revision: usize,
+}
+
+impl EtcdGetManyResponse {
+    /// Creates a new `EtcdGetManyResponse`.
+    pub(super) const fn new(kvs: HashMap<Vec<u8>, EtcdKeyValue>, revision: usize) -> Self {
+        Self { kvs, revision }
+    }
+
+    /// Gets the revision the keys are read at.
+    #[inline]
+    #[must_use]
+    pub const fn revision(&self) -> usize {
+        self.revision
+    }
+
+    /// Gets the key-value pair of a key, `None` if the key doesn't exist.
+    #[inline]
+    #[must_use]
+    pub fn get(&self, key: &[u8]) -> Option<&EtcdKeyValue> {
+        self.kvs.get(key)
+    }
+
+    /// Takes the key-value pairs out of response, leaving an empty map in its place.
+    #[inline]
+    pub fn take_kvs(&mut self) -> HashMap<Vec<u8>, EtcdKeyValue> {
+        std::mem::take(&mut self.kvs)
+    }
+}
@@ -0,0 +1,107 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_stream::try_stream;
+use clippy_utilities::Cast;
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
+use grpcio::WriteFlags;
+use log::warn;
+
+use super::{EtcdKeyValue, EtcdWatchRequest, KeyRange, Kv};
+use crate::{CallOptions, EtcdError, Event, OverflowArithmetic, Result};
+
+/// The max number of keys in a snapshot chunk.
+const MIRROR_PAGE_SIZE: usize = 1000;
+
+/// An item of the mirror stream.
+#[non_exhaustive]
+pub enum MirrorItem {
+    /// A new snapshot at the revision starts, the local copy should be cleared.
+    /// It is sent before the first snapshot, and before the key range is listed again
+    /// when the watch falls behind the compaction or the watch stream is closed.
+    Reset(usize),
+    /// A chunk of the key-value pairs of the snapshot, ordered by key.
+    Chunk(Vec<EtcdKeyValue>),
+    /// The events after the snapshot, with the revision of the response.
+    Update(usize, Vec<Event>),
+}
+
+/// A stream which mirrors a key range, by listing the key range at a revision and then
+/// watching the changes after the revision.
+pub struct MirrorStream<'a> {
+    /// The items of the mirror.
+    inner: Pin<Box<dyn Stream<Item = Result<MirrorItem>> + Send + 'a>>,
+}
+
+impl<'a> MirrorStream<'a> {
+    /// Creates a new `MirrorStream`.
+    pub(super) fn new(kv: &'a Kv, key_range: KeyRange) -> Self {
+        let inner = try_stream! {
+            loop {
+                // List the key range at a pinned revision
+                let mut snapshot = kv.range_stream(key_range.clone(), MIRROR_PAGE_SIZE);
+                let mut next = snapshot.try_next().await?;
+                let revision = snapshot.revision().ok_or_else(|| {
+                    EtcdError::InternalError("range response has no header".to_owned())
+                })?;
+                yield MirrorItem::Reset(revision);
+                let mut chunk = Vec::new();
+                while let Some(key_value) = next {
+                    chunk.push(key_value);
+                    if chunk.len() >= MIRROR_PAGE_SIZE {
+                        yield MirrorItem::Chunk(std::mem::take(&mut chunk));
+                    }
+                    next = snapshot.try_next().await?;
+                }
+                if !chunk.is_empty() {
+                    yield MirrorItem::Chunk(chunk);
+                }
+
+                // Watch the changes after the snapshot
+                let (mut req_sender, mut resp_receiver) = kv
+                    .watch_client
+                    .watch_opt(kv.token.call_option(&CallOptions::default())?)?;
+                let mut req = EtcdWatchRequest::create(key_range.clone());
+                req.set_start_revision(revision.overflow_add(1));
+                req_sender.send((req.into(), WriteFlags::default())).await?;
+                while let Some(resp) = resp_receiver.next().await {
+                    let mut resp = resp?;
+                    if resp.get_compact_revision() > 0 {
+                        warn!(
+                            "Mirror of {} is compacted at revision {}, list the key range again",
+                            key_range,
+                            resp.get_compact_revision()
+                        );
+                        break;
+                    }
+                    if resp.get_canceled() {
+                        Err(EtcdError::InternalError(format!(
+                            "mirror watch is canceled, the reason is: {}",
+                            resp.get_cancel_reason()
+                        )))?;
+                    }
+                    let events = resp.take_events();
+                    if !events.is_empty() {
+                        let revision: usize = resp.get_header().get_revision().cast();
+                        yield MirrorItem::Update(
+                            revision,
+                            events.into_iter().map(Event::from).collect(),
+                        );
+                    }
+                }
+            }
+        };
+        Self {
+            inner: Box::pin(inner),
+        }
+    }
+}
+
+impl Stream for MirrorStream<'_> {
+    type Item = Result<MirrorItem>;
+
+    #[inline]
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
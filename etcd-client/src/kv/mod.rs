@@ -1,38 +1,64 @@
+/// Etcd bulk mod for bulk put and delete operations.
+mod bulk;
 mod cache;
+/// Etcd compact mod for compaction operations.
+mod compact;
 /// Etcd delete mod for delete operations.
 mod delete;
+/// Etcd diff mod for comparing snapshots of a key range.
+mod diff;
+/// Etcd dump mod for exporting and importing key ranges.
+mod dump;
+/// Etcd eviction mod for cache eviction policies.
+mod eviction;
 /// Etcd get mod for get operations.
 mod get;
+/// Etcd mirror mod for mirroring key ranges.
+mod mirror;
 /// Etcd put mod for put operations.
 mod put;
 /// Etcd range mod for range fetching operations.
 mod range;
+/// Etcd range cache mod for caching key ranges.
+mod range_cache;
+/// Etcd range stream mod for paginated range fetching.
+mod range_stream;
 /// Etcd txn mod for transaction operations.
 mod txn;
 
-pub use super::watch::{EtcdWatchRequest, EtcdWatchResponse};
+pub use super::watch::{EtcdWatchRequest, EtcdWatchResponse, KeyVersion};
 use async_std::channel::Receiver;
-pub use cache::Cache;
+pub(crate) use bulk::DEFAULT_MAX_TXN_OPS;
+pub use bulk::{BulkChunk, BulkOptions, BulkResponse};
+pub use cache::{Cache, CacheHit};
+pub use compact::{CompactionRetention, EtcdCompactRequest, EtcdCompactResponse};
 pub use delete::{EtcdDeleteRequest, EtcdDeleteResponse};
-pub use get::{EtcdGetRequest, EtcdGetResponse};
+pub use diff::KeyChange;
+pub use dump::{ImportMode, ImportOptions};
+pub use eviction::{
+    CacheCapacity, EvictionPolicy, EvictionPolicyKind, LfuPolicy, LruPolicy, WTinyLfuPolicy,
+};
+pub use get::{EtcdGetManyResponse, EtcdGetRequest, EtcdGetResponse};
+pub use mirror::{MirrorItem, MirrorStream};
 pub use put::{EtcdPutRequest, EtcdPutResponse};
-pub use range::{EtcdRangeRequest, EtcdRangeResponse};
+pub use range::{EtcdRangeRequest, EtcdRangeResponse, SortOrder, SortTarget};
+use range_cache::RangeCache;
+pub use range_stream::RangeStream;
+use txn::TxnOp;
 pub use txn::{EtcdTxnRequest, EtcdTxnResponse, TxnCmp, TxnOpResponse};
 
 use super::OverflowArithmetic;
+use crate::auth::{AuthToken, INVALID_TOKEN_REASON};
 use crate::protos::kv::Event_EventType;
-use crate::protos::rpc::{RangeResponse, WatchRequest};
+use crate::protos::rpc::{RangeResponse, WatchRequest, WatchResponse};
 use crate::protos::rpc_grpc::{KvClient, WatchClient};
-use crate::retryable;
-use crate::CURRENT_INTERVAL_ENV_KEY;
-use crate::CURRENT_INTERVAL_VALUE;
-use crate::INITIAL_INTERVAL_ENV_KEY;
-use crate::INITIAL_INTERVAL_VALUE;
-use crate::MAX_ELAPSED_TIME_ENV_KEY;
-use crate::MAX_ELAPSED_TIME_VALUE;
-use backoff::ExponentialBackoff;
+use crate::retry::ConflictBackoff;
+use crate::stm::{self, Isolation, StmFuture, StmTxn};
+use crate::watch;
+use crate::{CallOptions, EtcdError, RetryOperation, RetryPolicy};
 use either::{Left, Right};
 use futures::future::FutureExt;
+use futures::io::{AsyncBufRead, AsyncWrite};
 use futures::stream::StreamExt;
 use grpcio::{Error, StreamingCallSink, WriteFlags};
 use log::warn;
@@ -42,9 +68,11 @@ use smol::Timer;
 use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::fmt::{Debug, Display};
+use std::ops::Range;
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, Weak};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::protos::kv::KeyValue;
 use crate::Result as Res;
@@ -58,8 +86,14 @@ pub struct Kv {
     client: KvClient,
     /// Watch Client
     watch_client: WatchClient,
-    /// Kv Cache Size
-    cache_size: usize,
+    /// Auth token attached to RPCs.
+    token: Arc<AuthToken>,
+    /// Retry policy of RPCs.
+    retry_policy: Arc<RetryPolicy>,
+    /// Kv Cache capacity
+    cache_capacity: CacheCapacity,
+    /// Kv Cache eviction policy
+    eviction_policy: EvictionPolicyKind,
     /// Kv Cache if etcd client cache is enabled otherwise None
     kvcache: Option<ArcSwap<KvCache>>,
     /// Lock to restart cache
@@ -70,6 +104,8 @@ pub struct Kv {
 pub struct KvCache {
     /// Etcd client cache.
     cache: Arc<Cache>,
+    /// Cache of the registered key ranges.
+    ranges: RangeCache,
     /// Etcd watch request sender.
     watch_sender: Sender<LocalWatchRequest>,
     /// A channel sender to send shutdown request for task.
@@ -77,31 +113,53 @@ pub struct KvCache {
     shutdown_watch_task: Sender<()>,
     /// Arc to `Kv` that contains this `KvCache`
     kv: Weak<Kv>,
+    /// Whether the watches of the cache are live. It is false while the watch stream is
+    /// recovering, when the cached entries may be stale, so the cache is not read.
+    watching: AtomicBool,
 }
 
 /// Etcd client cache default size.
 const ETCD_CACHE_DEFAULT_SIZE: usize = 256;
+/// The interval to wait before reopening a failed watch stream of the cache.
+const CACHE_RECOVERY_INTERVAL: Duration = Duration::from_millis(100);
 impl KvCache {
     /// Creates a new `KvClient`.
     ///
     /// This method should only be called within etcd client.
-    pub fn new(watch_client: &WatchClient, cache_size: usize, kv: Weak<Kv>) -> Arc<Self> {
-        let etcd_cache_size = if cache_size == 0 {
-            ETCD_CACHE_DEFAULT_SIZE
+    pub fn new(
+        watch_client: WatchClient,
+        token: Arc<AuthToken>,
+        cache_capacity: CacheCapacity,
+        eviction_policy: &EvictionPolicyKind,
+        kv: Weak<Kv>,
+    ) -> Arc<Self> {
+        let cache_capacity = if cache_capacity.limit() == 0 {
+            CacheCapacity::Entries(ETCD_CACHE_DEFAULT_SIZE)
         } else {
-            cache_size
+            cache_capacity
+        };
+        // The number of keys a byte capacity holds is unknown, guess it by the default size
+        let entries = match cache_capacity {
+            CacheCapacity::Entries(entries) => entries,
+            CacheCapacity::Bytes(_) => ETCD_CACHE_DEFAULT_SIZE,
         };
 
-        let cache = Arc::new(Cache::new(etcd_cache_size));
+        let cache = Arc::new(Cache::new(
+            cache_capacity,
+            eviction_policy.build(entries),
+            entries,
+        ));
 
         let cache_clone = Arc::<Cache>::clone(&cache);
         let (watch_req_sender, watch_req_receiver) = unbounded::<LocalWatchRequest>();
         let (shutdown_tx, shutdown_rx) = unbounded();
         let this = Arc::new(Self {
             cache,
+            ranges: RangeCache::new(),
             watch_sender: watch_req_sender,
             shutdown_watch_task: shutdown_tx,
             kv,
+            watching: AtomicBool::new(true),
         });
 
         Self::start_watch_task(
@@ -110,11 +168,25 @@ impl KvCache {
             shutdown_rx,
             watch_req_receiver,
             watch_client,
+            token,
         );
 
         this
     }
 
+    /// Whether the cache is kept fresh by its watches and can be read.
+    fn is_watching(&self) -> bool {
+        self.watching.load(Ordering::Acquire)
+    }
+
+    /// Marks the watches of the cache live once the watch stream is `idle`, when no watch
+    /// request is waiting to be created again.
+    fn update_watching(&self, idle: bool) {
+        if idle {
+            self.watching.store(true, Ordering::Release);
+        }
+    }
+
     /// Restart cache
     fn restart_cache(&self) {
         if let Some(kv) = self.kv.upgrade() {
@@ -123,6 +195,12 @@ impl KvCache {
     }
 
     /// Start async watch task
+    ///
+    /// If the watch stream fails, the task recovers the cache instead of restarting it:
+    /// the stream is reopened, and every watch is created again starting from the revision
+    /// following the last one it has seen, so no change is missed and no entry is lost.
+    /// Only the entries whose history has been compacted are invalidated. The cache is not
+    /// read during the recovery, until every watch is created again.
     #[allow(clippy::mut_mut)]
     #[allow(clippy::too_many_lines)]
     fn start_watch_task(
@@ -130,195 +208,343 @@ impl KvCache {
         cache_clone: Arc<Cache>,
         shutdown_rx: Receiver<()>,
         watch_req_receiver: Receiver<LocalWatchRequest>,
-        watch_client: &WatchClient,
+        watch_client: WatchClient,
+        token: Arc<AuthToken>,
     ) {
-        let (mut client_req_sender, mut client_resp_receiver) = watch_client
-            .watch()
-            .unwrap_or_else(|e| panic!("failed to start watch channel, the error is: {}", e));
         smol::spawn(async move {
-            let mut watch_map = HashMap::<Vec<u8>, i64>::new();
+            let mut watch_map = HashMap::<(Vec<u8>, Vec<u8>), i64>::new();
+            // The created watches by watch id, the revision of each request is the
+            // revision to resume the watch from
+            let mut watch_ids = HashMap::<i64, LocalWatchRequest>::new();
             let mut shutdown_rx = shutdown_rx.into_future().fuse();
             let mut watch_request_queue = VecDeque::<LocalWatchRequest>::new();
-            let mut has_pending_watch_request = false;
-            let mut processing_req = None;
-
-            loop {
-                let message = futures::select! {
-                    watch_req_opt = watch_req_receiver.recv().fuse() => {
-                        if let Ok(req) = watch_req_opt {
-                            Left(req)
-                        } else {
-                            warn!("Failed to receive watch request");
-                            this_clone.restart_cache();
-                            return;
-                        }
+            let mut processing_req: Option<LocalWatchRequest> = None;
+            let mut recovering = false;
 
+            'stream: loop {
+                if recovering {
+                    this_clone.watching.store(false, Ordering::Release);
+                    Timer::after(CACHE_RECOVERY_INTERVAL).await;
+                    Self::resume_watches(
+                        &cache_clone,
+                        &mut watch_map,
+                        &mut watch_ids,
+                        &mut watch_request_queue,
+                        processing_req.take(),
+                    );
+                }
+                recovering = true;
+                let (mut client_req_sender, mut client_resp_receiver) = match token
+                    .call_option(&CallOptions::default())
+                    .and_then(|option| watch_client.watch_opt(option))
+                {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Fail to open watch stream, the error is: {}, recover cache", e);
+                        let _refreshed = token.refresh_on(&e).await;
+                        continue 'stream;
                     }
-                    watch_resp_opt = client_resp_receiver.next().fuse() => {
-                        if let Some(resp) = watch_resp_opt {
-                            Right(resp)
-                        } else {
-                            warn!("failed to receive watch response from etcd");
-                            this_clone.restart_cache();
-                            return;
-                        }
-                    }
-                    _ = shutdown_rx => return
-
                 };
+                let res = Self::send_next_watch_request(
+                    &cache_clone,
+                    &mut watch_request_queue,
+                    &watch_map,
+                    &mut processing_req,
+                    &mut client_req_sender,
+                )
+                .await;
+                if let Err(e) = res {
+                    warn!(
+                        "Fail to send watch request, the error is: {}, recover cache",
+                        e
+                    );
+                    continue 'stream;
+                }
+                this_clone.update_watching(processing_req.is_none());
+
+                loop {
+                    let message = futures::select! {
+                        watch_req_opt = watch_req_receiver.recv().fuse() => {
+                            if let Ok(req) = watch_req_opt {
+                                Left(req)
+                            } else {
+                                warn!("Failed to receive watch request");
+                                this_clone.restart_cache();
+                                return;
+                            }
 
-                match message {
-                    Left(watch_req) => {
-                        let watch_key = &watch_req.key;
-                        // If key is already watched, skip create watch request
-                        // If key is not watched, skip cancel watch request
-                        if (watch_req.is_create && watch_map.contains_key(watch_key))
-                            || (!watch_req.is_create && !watch_map.contains_key(watch_key))
-                        {
-                            continue;
                         }
-                        watch_request_queue.push_back(watch_req);
+                        watch_resp_opt = client_resp_receiver.next().fuse() => {
+                            if let Some(resp) = watch_resp_opt {
+                                Right(resp)
+                            } else {
+                                warn!("failed to receive watch response from etcd, recover cache");
+                                continue 'stream;
+                            }
+                        }
+                        _ = shutdown_rx => return
+
+                    };
 
-                        if !has_pending_watch_request {
-                            if let Some(req) = watch_request_queue.pop_front() {
-                                processing_req = Some(req);
-                                has_pending_watch_request = true;
-                                let res = Self::send_watch_requset(processing_req.as_ref(), &watch_map, &mut client_req_sender).await;
+                    match message {
+                        Left(watch_req) => {
+                            let watch_key = watch_req.watch_key();
+                            // If key is already watched, skip create watch request
+                            if watch_req.is_create && watch_map.contains_key(&watch_key) {
+                                continue;
+                            }
+                            // If key is not watched, drop its create request waiting in the
+                            // queue, such as a watch to resume, instead of the cancel request
+                            if !watch_req.is_create && !watch_map.contains_key(&watch_key) {
+                                let queue_len = watch_request_queue.len();
+                                watch_request_queue.retain(|req| req.watch_key() != watch_key);
+                                if watch_request_queue.len() != queue_len && watch_req.range_end.is_empty() {
+                                    cache_clone.remove(&watch_req.key);
+                                }
+                                continue;
+                            }
+                            watch_request_queue.push_back(watch_req);
+
+                            if processing_req.is_none() {
+                                let res = Self::send_next_watch_request(
+                                    &cache_clone,
+                                    &mut watch_request_queue,
+                                    &watch_map,
+                                    &mut processing_req,
+                                    &mut client_req_sender,
+                                )
+                                .await;
                                 if let Err(e) = res {
                                     warn!(
-                                        "Fail to send watch request, the error is: {}, restart cache",
+                                        "Fail to send watch request, the error is: {}, recover cache",
                                         e
                                     );
-                                    this_clone.restart_cache();
-                                    return;
+                                    continue 'stream;
                                 }
+                                this_clone.update_watching(processing_req.is_none());
                             }
-                        }
-                    },
-                    Right(watch_resp) => {
-                        match watch_resp {
-                            Ok(resp) => {
-                                if resp.get_created() || resp.get_canceled() {
-                                    if let Some(req) = processing_req.take() {
-                                        let watch_id = resp.get_watch_id();
-                                        let is_create = resp.get_created();
-                                        if is_create != req.is_create {
-                                            warn!(
-                                                "processing request is_create {} doesn't match response is_create {},
-                                                 restart cache",
-                                                is_create, req.is_create
-                                            );
-                                            this_clone.restart_cache();
-                                            return;
+                        },
+                        Right(watch_resp) => {
+                            match watch_resp {
+                                Ok(resp) => {
+                                    if resp.get_compact_revision() > 0 {
+                                        // The watch is cancelled by etcd as the revision to
+                                        // watch from is compacted
+                                        if let Some(req) = watch_ids.remove(&resp.get_watch_id()) {
+                                            watch_map.remove(&req.watch_key());
+                                            this_clone.invalidate(&cache_clone, req).await;
                                         }
-                                        let processing_key = req.key.clone();
-                                        if is_create {
-                                            watch_map.insert(processing_key, watch_id);
-                                        } else {
-                                            watch_map.remove(&processing_key);
-                                            cache_clone.remove(&processing_key).await;
+                                    } else if resp.get_created() && resp.get_canceled() {
+                                        // The watch is rejected, such as the auth token the stream
+                                        // is opened with is expired, the stream is opened again
+                                        // with the refreshed token to create the watch
+                                        warn!(
+                                            "Watch is rejected, the reason is: {}, recover cache",
+                                            resp.get_cancel_reason()
+                                        );
+                                        if resp.get_cancel_reason().ends_with(INVALID_TOKEN_REASON) {
+                                            let _refreshed = token.refresh().await;
                                         }
-                                        has_pending_watch_request = false;
-                                        if let Some(req) = watch_request_queue.pop_front() {
-                                            processing_req = Some(req);
-                                            has_pending_watch_request = true;
-                                            let res = Self::send_watch_requset(processing_req.as_ref(), &watch_map, &mut client_req_sender).await;
+                                        continue 'stream;
+                                    } else if resp.get_created() || resp.get_canceled() {
+                                        if let Some(req) = processing_req.take() {
+                                            let watch_id = resp.get_watch_id();
+                                            let is_create = resp.get_created();
+                                            if is_create != req.is_create {
+                                                warn!(
+                                                    "processing request is_create {} doesn't match response is_create {},
+                                                     recover cache",
+                                                    is_create, req.is_create
+                                                );
+                                                processing_req = Some(req);
+                                                continue 'stream;
+                                            }
+                                            let processing_key = req.watch_key();
+                                            if is_create {
+                                                watch_map.insert(processing_key, watch_id);
+                                                watch_ids.insert(watch_id, req);
+                                            } else {
+                                                watch_map.remove(&processing_key);
+                                                if let Some(created) = watch_ids.remove(&watch_id) {
+                                                    if created.range_end.is_empty() {
+                                                        cache_clone.remove(&created.key);
+                                                    }
+                                                }
+                                            }
+                                            let res = Self::send_next_watch_request(
+                                                &cache_clone,
+                                                &mut watch_request_queue,
+                                                &watch_map,
+                                                &mut processing_req,
+                                                &mut client_req_sender,
+                                            )
+                                            .await;
                                             if let Err(e) = res {
                                                 warn!(
-                                                    "Fail to send watch request, the error is: {}, restart cache",
+                                                    "Fail to send watch request, the error is: {}, recover cache",
                                                     e
                                                 );
-                                                this_clone.restart_cache();
-                                                return;
+                                                continue 'stream;
                                             }
-                                        }
-                                    } else {
-                                        warn!(
-                                            "Receive watch response when no watch request is sent, the watch response is: {:?}, restart cache",
-                                            resp
-                                        );
-                                        this_clone.restart_cache();
-                                        return;
-                                    }
-                                } else {
-                                    let events = resp.get_events().to_vec();
-                                    for event in events {
-                                        if event.get_field_type() == Event_EventType::PUT {
-                                            cache_clone
-                                                .insert_or_update(
-                                                    event.get_kv().get_key().to_vec(),
-                                                    event.get_kv().clone(),
-                                                ).await;
+                                            this_clone.update_watching(processing_req.is_none());
                                         } else {
-                                            cache_clone
-                                                .mark_delete(
-                                                    event.get_kv().get_key().to_vec(),
-                                                    event.get_kv().clone(),
-                                                ).await;
-                                            {
-                                                let res = this_clone
-                                                    .watch_sender
-                                                    .send(LocalWatchRequest::cancel(
-                                                        event.get_kv().get_key().to_vec(),
-                                                    ))
-                                                    .await;
-                                                if let Err(e) = res {
-                                                    warn!(
-                                                        "Fail to send watch request, the error is: {}, restart cache",
-                                                        e
-                                                    );
-                                                    this_clone.restart_cache();
-                                                    return;
+                                            warn!(
+                                                "Receive watch response when no watch request is sent, the watch response is: {:?}, recover cache",
+                                                resp
+                                            );
+                                            continue 'stream;
+                                        }
+                                    } else if let Some(req) = watch_ids.get_mut(&resp.get_watch_id()) {
+                                        req.advance(&resp);
+                                        if req.range_end.is_empty() {
+                                            let events = resp.get_events().to_vec();
+                                            for event in events {
+                                                if event.get_field_type() == Event_EventType::PUT {
+                                                    cache_clone
+                                                        .insert_or_update(
+                                                            event.get_kv().get_key().to_vec(),
+                                                            event.get_kv().clone(),
+                                                        );
+                                                } else {
+                                                    // The deleted key is cached as absent and
+                                                    // still watched until it is put again
+                                                    let _marked = cache_clone
+                                                        .mark_absent(
+                                                            event.get_kv().get_key().to_vec(),
+                                                            event.get_kv().get_mod_revision(),
+                                                        );
                                                 }
                                             }
+                                        } else {
+                                            let key_range = KeyRange::range(req.key.clone(), req.range_end.clone());
+                                            this_clone
+                                                .ranges
+                                                .apply(&key_range, resp.get_events(), resp.get_header().get_revision())
+                                                .await;
                                         }
+                                    } else {
+                                        warn!("Receive watch events of unknown watch {}", resp.get_watch_id());
                                     }
                                 }
+                                Err(e) => {
+                                    warn!(
+                                        "Watch response contains error, the error is: {}, recover cache",
+                                        e
+                                    );
+                                    let _refreshed = token.refresh_on(&e).await;
+                                    continue 'stream;
+                                }
                             }
-                            Err(e) => {
-                                warn!(
-                                    "Watch response contains error, the error is: {}, restart cache",
-                                    e
-                                );
-                                this_clone.restart_cache();
-                                return;
-                            }
-                        }
-                    },
+                        },
+                    }
                 }
             }
         })
         .detach();
     }
 
-    /// Send watch request to etcd server
-    async fn send_watch_requset(
-        watch_req: Option<&LocalWatchRequest>,
-        watch_map: &HashMap<Vec<u8>, i64>,
+    /// Prepares to resume the watches on a new watch stream. The created watches are queued
+    /// to be created again from the revisions they have reached, ahead of the requests not
+    /// handled yet. A pending cancel request is applied at once, as its watch doesn't
+    /// exist on the new stream.
+    fn resume_watches(
+        cache: &Cache,
+        watch_map: &mut HashMap<(Vec<u8>, Vec<u8>), i64>,
+        watch_ids: &mut HashMap<i64, LocalWatchRequest>,
+        watch_request_queue: &mut VecDeque<LocalWatchRequest>,
+        processing_req: Option<LocalWatchRequest>,
+    ) {
+        watch_map.clear();
+        let mut resumed = watch_ids
+            .drain()
+            .map(|(_, req)| (req.watch_key(), req))
+            .collect::<HashMap<_, _>>();
+        let mut pending = VecDeque::new();
+        for req in processing_req
+            .into_iter()
+            .chain(watch_request_queue.drain(..))
+        {
+            if req.is_create {
+                pending.push_back(req);
+                continue;
+            }
+            // The watch to cancel may be not created yet, drop its pending create request
+            let watch_key = req.watch_key();
+            let _created = resumed.remove(&watch_key);
+            pending.retain(|pending_req: &LocalWatchRequest| pending_req.watch_key() != watch_key);
+            if req.range_end.is_empty() {
+                cache.remove(&req.key);
+            }
+        }
+        watch_request_queue.extend(resumed.into_values());
+        watch_request_queue.append(&mut pending);
+    }
+
+    /// Invalidates the entries of a watch whose history is compacted. A key is removed from
+    /// the cache, and a key range is loaded again.
+    async fn invalidate(&self, cache: &Cache, req: LocalWatchRequest) {
+        if req.range_end.is_empty() {
+            warn!(
+                "The history of key {:?} is compacted, remove it from cache",
+                req.key
+            );
+            cache.remove(&req.key);
+            return;
+        }
+        let key_range = KeyRange::range(req.key, req.range_end);
+        warn!(
+            "The history of key range {} is compacted, load it again",
+            key_range
+        );
+        self.ranges.unregister(&key_range).await;
+        if let Some(kv) = self.kv.upgrade() {
+            smol::spawn(async move {
+                if let Err(e) = kv.cache_range(key_range).await {
+                    warn!("Fail to load key range again, the error is: {}", e);
+                }
+            })
+            .detach();
+        }
+    }
+
+    /// Sends the next watch request in the queue to etcd server, the sent request is kept
+    /// in `processing_req` until its response arrives. A cancel request whose watch doesn't
+    /// exist, such as a watch cancelled by etcd for compaction, is applied at once.
+    async fn send_next_watch_request(
+        cache: &Cache,
+        watch_request_queue: &mut VecDeque<LocalWatchRequest>,
+        watch_map: &HashMap<(Vec<u8>, Vec<u8>), i64>,
+        processing_req: &mut Option<LocalWatchRequest>,
         client_req_sender: &mut StreamingCallSink<WatchRequest>,
     ) -> Result<(), Error> {
-        if let Some(req) = watch_req {
-            let processing_key = req.key.clone();
+        while let Some(req) = watch_request_queue.pop_front() {
             let request = if req.is_create {
-                let mut etcd_req = EtcdWatchRequest::create(KeyRange::key(processing_key));
+                let mut etcd_req = EtcdWatchRequest::create(KeyRange::range(
+                    req.key.clone(),
+                    req.range_end.clone(),
+                ));
                 etcd_req.set_start_revision(req.revision.cast());
+                // Keep the revision to resume from moving even if the watched keys have no
+                // change, so an idle watch is not cancelled for compaction when resumed
+                etcd_req.set_progress_notify(true);
                 etcd_req
             } else {
-                let etcd_req = EtcdWatchRequest::cancel(
-                    (*watch_map
-                        .get(&processing_key)
-                        .unwrap_or_else(|| panic!("key {:?} doesn't exist", processing_key)))
-                    .cast(),
-                );
-                etcd_req
+                match watch_map.get(&req.watch_key()) {
+                    Some(&watch_id) => EtcdWatchRequest::cancel(watch_id.cast()),
+                    None => {
+                        if req.range_end.is_empty() {
+                            cache.remove(&req.key);
+                        }
+                        continue;
+                    }
+                }
             };
-
-            client_req_sender
+            *processing_req = Some(req);
+            return client_req_sender
                 .send((request.into(), WriteFlags::default()))
-                .await
-        } else {
-            Ok(())
+                .await;
         }
+        Ok(())
     }
 
     /// Shutdown cache task
@@ -349,21 +575,29 @@ impl Kv {
     pub(crate) fn new(
         client: KvClient,
         watch_client: WatchClient,
-        cache_size: usize,
+        token: Arc<AuthToken>,
+        retry_policy: Arc<RetryPolicy>,
+        cache_capacity: CacheCapacity,
+        eviction_policy: EvictionPolicyKind,
         cache_enable: bool,
     ) -> Arc<Self> {
         let this = Arc::new(Self {
             client,
             watch_client,
-            cache_size,
+            token,
+            retry_policy,
+            cache_capacity,
+            eviction_policy,
             kvcache: None,
             restart_lock: Mutex::<()>::new(()),
         });
 
         if cache_enable {
             let kvcache = Some(ArcSwap::from(KvCache::new(
-                &this.watch_client,
-                cache_size,
+                this.watch_client.clone(),
+                Arc::clone(&this.token),
+                this.cache_capacity,
+                &this.eviction_policy,
                 Arc::<Self>::downgrade(&this),
             )));
             // SAFETY: it is safe because this is constructor.
@@ -380,7 +614,13 @@ impl Kv {
         if self.restart_lock.try_lock().is_ok() {
             if let Some(ref kvcache) = self.kvcache {
                 let self_weak = Weak::<Self>::clone(&kvcache.load().kv);
-                let new_kvcache = KvCache::new(&self.watch_client, self.cache_size, self_weak);
+                let new_kvcache = KvCache::new(
+                    self.watch_client.clone(),
+                    Arc::clone(&self.token),
+                    self.cache_capacity,
+                    &self.eviction_policy,
+                    self_weak,
+                );
                 kvcache.store(new_kvcache);
             }
         }
@@ -393,19 +633,19 @@ impl Kv {
     /// Will return `Err` if RPC call is failed.
     #[inline]
     pub async fn put(&self, req: EtcdPutRequest) -> Res<EtcdPutResponse> {
-        let resp: EtcdPutResponse = retryable!(|| async {
-            let resp = self.client.put_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
+        let options = req.call_options().start();
+        let resp: EtcdPutResponse = self
+            .retry_policy
+            .retry(RetryOperation::Put, options.deadline, || async {
+                let resp = self
+                    .client
+                    .put_async_opt(&req.clone().into(), self.token.call_option(&options)?)?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
         // Wait until cache is updated and then return
-        if let Some(ref kvcache) = self.kvcache {
-            while let Some(kv) = kvcache.load().cache.search(req.get_key()).await {
-                if kv.get_mod_revision() >= resp.get_revision() {
-                    break;
-                }
-                Timer::after(Duration::from_millis(1)).await;
-            }
-        }
+        self.wait_cache_updated(req.get_key(), resp.get_revision(), options.deadline)
+            .await;
         Ok(resp)
     }
 
@@ -416,57 +656,166 @@ impl Kv {
     /// Will return `Err` if RPC call is failed.
     #[inline]
     pub async fn get(&self, req: EtcdGetRequest) -> Res<EtcdGetResponse> {
-        // cache is enabled
-        if let Some(ref kvcache) = self.kvcache {
-            if let Some(value) = kvcache.load().cache.search(req.get_key()).await {
-                let mut response = RangeResponse::new();
-                response.set_count(1);
-                response.set_kvs(RepeatedField::from_vec(vec![value]));
+        // The cache may be stale while its watches are recovering
+        let watching = self
+            .kvcache
+            .as_ref()
+            .map_or(false, |kvcache| kvcache.load().is_watching());
+        if let Some(kvcache) = self.kvcache.as_ref().filter(|_| watching) {
+            if let Some(response) = kvcache.load().ranges.search(&req.clone().into()).await {
                 return Ok(EtcdGetResponse::new(response));
             }
         }
 
-        let resp = retryable!(|| async {
-            let resp = self.client.range_async(&req.clone().into())?;
-            Ok(resp.await?)
-        });
+        // cache is enabled and the request reads the latest value
+        let cacheable = req.is_cacheable();
+        if let Some(kvcache) = self.kvcache.as_ref().filter(|_| cacheable && watching) {
+            match kvcache.load().cache.search(req.get_key()) {
+                Some(CacheHit::Present(value)) => {
+                    let mut response = RangeResponse::new();
+                    response.set_count(1);
+                    response.set_kvs(RepeatedField::from_vec(vec![value]));
+                    return Ok(EtcdGetResponse::new(response));
+                }
+                Some(CacheHit::Absent) => return Ok(EtcdGetResponse::new(RangeResponse::new())),
+                None => {}
+            }
+        }
 
-        if let Some(ref kvcache_arc) = self.kvcache {
-            let kvs = resp.get_kvs();
+        let options = req.call_options().start();
+        let resp = self
+            .retry_policy
+            .retry(RetryOperation::Get, options.deadline, || async {
+                let resp = self
+                    .client
+                    .range_async_opt(&req.clone().into(), self.token.call_option(&options)?)?;
+                Ok(self.token.check(resp.await).await?)
+            })
+            .await?;
+
+        if let Some(kvcache_arc) = self.kvcache.as_ref().filter(|_| cacheable) {
             let kvcache = kvcache_arc.load();
-            for kv in kvs {
-                let (succeed, is_insert) = kvcache
-                    .cache
-                    .insert_or_update(kv.get_key().to_vec(), kv.clone())
-                    .await;
-                if succeed && is_insert {
-                    // Creates a new watch request and adds to the send queue.
-                    let watch_request =
-                        LocalWatchRequest::create(kv.get_key().to_vec(), kv.get_mod_revision());
-                    if let Err(e) = kvcache.watch_sender.send(watch_request).await {
-                        warn!(
-                            "Fail to send watch request, the error is {}, restart cache",
-                            e
-                        );
-                        self.restart_kvcache();
-                        return Err(e.into());
-                    }
+            let key = req.get_key().to_vec();
+            // The key is unchanged up to the revision of the response, so it is watched
+            // from the next revision rather than its mod revision, which may be compacted
+            let revision = resp.get_header().get_revision();
+            let (succeed, is_insert) = match resp.get_kvs().first() {
+                Some(kv) => kvcache.cache.insert_or_update(key.clone(), kv.clone()),
+                // A missing key is cached as absent at the revision of the response,
+                // and watched until it is put
+                None => kvcache.cache.mark_absent(key.clone(), revision),
+            };
+            if succeed && is_insert {
+                // Creates a new watch request and adds to the send queue.
+                let watch_request = LocalWatchRequest::create(key, revision.overflow_add(1));
+                if let Err(e) = kvcache.watch_sender.send(watch_request).await {
+                    warn!(
+                        "Fail to send watch request, the error is {}, restart cache",
+                        e
+                    );
+                    self.restart_kvcache();
+                    return Err(e.into());
+                }
 
-                    // Adjust cache size
-                    if let Err(e) = kvcache.cache.adjust_cache_size(&kvcache.watch_sender).await {
-                        warn!(
-                            "Fail to send watch request, the error is {}, restart cache",
-                            e
-                        );
-                        self.restart_kvcache();
-                        return Err(e);
-                    }
+                // Adjust cache size
+                if let Err(e) = kvcache.cache.adjust_cache_size(&kvcache.watch_sender).await {
+                    warn!(
+                        "Fail to send watch request, the error is {}, restart cache",
+                        e
+                    );
+                    self.restart_kvcache();
+                    return Err(e);
                 }
             }
         }
         Ok(From::from(resp))
     }
 
+    /// Gets several keys at a single revision in one round trip.
+    ///
+    /// The keys are read in a single txn. The keys found in the cache are not read again,
+    /// instead the txn checks that their mod revisions are unchanged, and all the keys are
+    /// read if any cached value is stale. The number of keys should not exceed the default
+    /// `--max-txn-ops` of etcd (128), read more keys by several calls instead.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed, or [`EtcdError::TxnOpsExceeded`] if there
+    /// are more than 128 keys.
+    #[inline]
+    pub async fn get_many<I, K>(&self, keys: I) -> Res<EtcdGetManyResponse>
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<Vec<u8>>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect::<Vec<Vec<u8>>>();
+        // Every key is read by the failure operations when any cached value is stale
+        if keys.len() > DEFAULT_MAX_TXN_OPS {
+            return Err(EtcdError::TxnOpsExceeded(keys.len(), DEFAULT_MAX_TXN_OPS));
+        }
+        let mut cached = Vec::new();
+        let mut absent = Vec::new();
+        let mut uncached = Vec::new();
+        for key in keys {
+            let hit = match self.kvcache {
+                Some(ref kvcache) => kvcache.load().cache.search(&key),
+                None => None,
+            };
+            match hit {
+                Some(CacheHit::Present(kv)) => cached.push(kv),
+                Some(CacheHit::Absent) => absent.push(key),
+                None => uncached.push(key),
+            }
+        }
+
+        let mut txn = EtcdTxnRequest::new();
+        for kv in &cached {
+            txn = txn.when_mod_revision(
+                KeyRange::key(kv.get_key()),
+                TxnCmp::Equal,
+                kv.get_mod_revision().cast(),
+            );
+        }
+        // The mod revision of a key that doesn't exist is 0
+        for key in &absent {
+            txn = txn.when_mod_revision(KeyRange::key(key.clone()), TxnCmp::Equal, 0);
+        }
+        for key in &uncached {
+            txn = txn.and_then(EtcdRangeRequest::new(KeyRange::key(key.clone())));
+        }
+        // Read all the keys if any cached value is stale
+        for key in cached
+            .iter()
+            .map(KeyValue::get_key)
+            .chain(absent.iter().map(Vec::as_slice))
+            .chain(uncached.iter().map(Vec::as_slice))
+        {
+            txn = txn.or_else(EtcdRangeRequest::new(KeyRange::key(key)));
+        }
+
+        let mut resp = self.txn(txn).await?;
+        let revision: usize = resp
+            .take_header()
+            .map(|header| header.revision().cast())
+            .ok_or_else(|| EtcdError::InternalError("txn response has no header".to_owned()))?;
+        let mut kvs = HashMap::new();
+        if resp.is_success() {
+            for kv in cached {
+                let _prev = kvs.insert(kv.get_key().to_vec(), EtcdKeyValue::from(kv));
+            }
+        }
+        for mut range_resp in resp
+            .take_responses()
+            .into_iter()
+            .filter_map(TxnOpResponse::into_range)
+        {
+            for kv in range_resp.take_kvs() {
+                let _prev = kvs.insert(kv.key().to_vec(), kv);
+            }
+        }
+        Ok(EtcdGetManyResponse::new(kvs, revision))
+    }
+
     /// Performs a range key-value fetching operation.
     ///
     /// # Errors
@@ -476,16 +825,150 @@ impl Kv {
     pub async fn range(&self, req: EtcdRangeRequest) -> Res<EtcdRangeResponse> {
         // If the request is single key, use get() instead
         if req.is_single_key() {
-            let resp = self
-                .get(EtcdGetRequest::new(req.get_key_range().take_key()))
-                .await?;
+            let resp = self.get(req.into_get_request()).await?;
             return Ok(resp.get_inner().into());
         }
-        let resp = retryable!(|| async {
-            let resp = self.client.range_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
-        Ok(resp)
+        if let Some(ref kvcache) = self.kvcache {
+            let kvcache = kvcache.load();
+            if kvcache.is_watching() {
+                if let Some(response) = kvcache.ranges.search(&req.clone().into()).await {
+                    return Ok(response.into());
+                }
+            }
+        }
+        self.range_rpc(req).await
+    }
+
+    /// Caches a key range as a whole, such as a prefix. The key range is loaded once and
+    /// kept fresh by a range watch, then the reads within the key range at the latest
+    /// revision, with any limit, sort, keys-only or count-only options, are answered by the
+    /// cache, including the reads of the keys that don't exist.
+    ///
+    /// It does nothing if the cache is disabled or the key range is already cached.
+    /// The key range is loaded in a single range request, so it should not be too large.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn cache_range(&self, key_range: KeyRange) -> Res<()> {
+        let kvcache = match self.kvcache {
+            Some(ref kvcache) => kvcache.load_full(),
+            None => return Ok(()),
+        };
+        if !kvcache.ranges.register(key_range.clone()).await {
+            return Ok(());
+        }
+        let mut resp = match self
+            .range_rpc(EtcdRangeRequest::new(key_range.clone()))
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                kvcache.ranges.unregister(&key_range).await;
+                return Err(e);
+            }
+        };
+        let revision: i64 = resp
+            .take_header()
+            .map(|header| header.revision().cast())
+            .ok_or_else(|| EtcdError::InternalError("range response has no header".to_owned()))?;
+        let kvs = resp.take_kvs().into_iter().map(|kv| kv.proto).collect();
+        kvcache.ranges.load(&key_range, kvs, revision).await;
+
+        let watch_request = LocalWatchRequest::create_range(key_range, revision.overflow_add(1));
+        if let Err(e) = kvcache.watch_sender.send(watch_request).await {
+            warn!(
+                "Fail to send watch request, the error is {}, restart cache",
+                e
+            );
+            self.restart_kvcache();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Stops caching a key range cached by [`Kv::cache_range`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the watch of the key range fails to be cancelled.
+    #[inline]
+    pub async fn uncache_range(&self, key_range: KeyRange) -> Res<()> {
+        if let Some(ref kvcache) = self.kvcache {
+            let kvcache = kvcache.load();
+            if kvcache.ranges.unregister(&key_range).await {
+                kvcache
+                    .watch_sender
+                    .send(LocalWatchRequest::cancel_range(key_range))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the key-value pairs of a key range page by page, `page_size` is the max
+    /// number of keys in a page. All the pages are read at the revision of the first page.
+    ///
+    /// Use this instead of [`Kv::range`] for very large ranges, whose response may exceed
+    /// the max gRPC message size.
+    #[inline]
+    #[must_use]
+    pub fn range_stream(&self, key_range: KeyRange, page_size: usize) -> RangeStream<'_> {
+        self.range_stream_at(key_range, page_size, 0)
+    }
+
+    /// Mirrors a key range. The key range is listed at a revision in chunks, and then the
+    /// changes after the revision are watched, so applying the items in order keeps a local
+    /// copy of the key range up to date. If the watch falls behind the compaction, the key
+    /// range is listed again after a [`MirrorItem::Reset`].
+    ///
+    /// The stream ends after yielding an error.
+    #[inline]
+    #[must_use]
+    pub fn mirror(&self, key_range: KeyRange) -> MirrorStream<'_> {
+        MirrorStream::new(self, key_range)
+    }
+
+    /// Compares the snapshots of a key range at `old_revision` and `new_revision`, returns
+    /// the keys added, modified and deleted in between ordered by key. The latest revision
+    /// is read if a revision is 0.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed, or either revision is compacted.
+    #[inline]
+    pub async fn diff(
+        &self,
+        key_range: KeyRange,
+        old_revision: usize,
+        new_revision: usize,
+    ) -> Res<Vec<KeyChange>> {
+        diff::diff(self, key_range, old_revision, new_revision).await
+    }
+
+    /// Fetches the key-value pairs of a key range page by page at `revision`,
+    /// the latest revision is read if `revision` is 0.
+    pub(crate) fn range_stream_at(
+        &self,
+        key_range: KeyRange,
+        page_size: usize,
+        revision: usize,
+    ) -> RangeStream<'_> {
+        RangeStream::new(self, key_range, page_size, revision)
+    }
+
+    /// Performs a range RPC, the cache is not checked.
+    pub(crate) async fn range_rpc(&self, req: EtcdRangeRequest) -> Res<EtcdRangeResponse> {
+        let options = req.call_options().start();
+        self.retry_policy
+            .retry(RetryOperation::Range, options.deadline, || async {
+                let resp = self
+                    .client
+                    .range_async_opt(&req.clone().into(), self.token.call_option(&options)?)?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await
     }
 
     /// Performs a key-value deleting operation.
@@ -499,24 +982,27 @@ impl Kv {
         if self.kvcache.is_some() {
             req.set_prev_kv(true);
         };
-        let mut resp: EtcdDeleteResponse = retryable!(|| async {
-            let resp = self.client.delete_range_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
+        let options = req.call_options().start();
+        let mut resp: EtcdDeleteResponse = self
+            .retry_policy
+            .retry(RetryOperation::Delete, options.deadline, || async {
+                let resp = self.client.delete_range_async_opt(
+                    &req.clone().into(),
+                    self.token.call_option(&options)?,
+                )?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
         // Wait until cache is updated and then return
-        if let Some(ref kvcache) = self.kvcache {
+        if self.kvcache.is_some() {
             let prev_kv = if request_prev_kv {
                 resp.get_prev_kvs()
             } else {
                 resp.take_prev_kvs()
             };
             for kv in prev_kv {
-                while let Some(kv) = kvcache.load().cache.search(kv.key()).await {
-                    if kv.get_mod_revision() >= resp.get_revision() {
-                        break;
-                    }
-                    Timer::after(Duration::from_millis(1)).await;
-                }
+                self.wait_cache_updated(kv.key(), resp.get_revision(), options.deadline)
+                    .await;
             }
         }
         Ok(resp)
@@ -529,13 +1015,451 @@ impl Kv {
     /// Will return `Err` if RPC call is failed.
     #[inline]
     pub async fn txn(&self, req: EtcdTxnRequest) -> Res<EtcdTxnResponse> {
-        let resp = retryable!(|| async {
-            let resp = self.client.txn_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
+        let options = req.call_options().start();
+        let resp = self
+            .retry_policy
+            .retry(RetryOperation::Txn, options.deadline, || async {
+                let resp = self
+                    .client
+                    .txn_async_opt(&req.clone().into(), self.token.call_option(&options)?)?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
         Ok(resp)
     }
 
+    /// Puts the key-value pairs in bulk. The puts are packed into txns within the limits of
+    /// `options`, and the txns are sent concurrently, so the puts of different txns are not
+    /// applied atomically unless [`BulkOptions::atomic`] is set.
+    ///
+    /// The result of each txn is reported in the response, a failed txn doesn't stop others.
+    ///
+    /// As etcd rejects a txn with duplicate keys, a put of a key already put in the txn
+    /// starts a new txn. A txn putting a key of an earlier txn is sent after the earlier
+    /// txn is done, so the later put of the key wins.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the atomic mode is set but the puts don't fit in a single txn,
+    /// or [`EtcdError::OverlappingTxnOps`] if they put a key more than once.
+    #[inline]
+    pub async fn put_many<I>(&self, reqs: I, options: BulkOptions) -> Res<BulkResponse>
+    where
+        I: IntoIterator<Item = EtcdPutRequest>,
+    {
+        let ops = reqs
+            .into_iter()
+            .map(|req| {
+                (
+                    KeyRange::key(req.get_key()),
+                    req.encoded_len(),
+                    TxnOp::from(req),
+                )
+            })
+            .collect();
+        self.bulk(ops, options).await
+    }
+
+    /// Deletes the key ranges in bulk, the deletes are packed into txns the same as
+    /// [`Kv::put_many`], and the overlapping key ranges are deleted in different txns.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the atomic mode is set but the deletes don't fit in a single txn,
+    /// or [`EtcdError::OverlappingTxnOps`] if the key ranges overlap.
+    #[inline]
+    pub async fn delete_many<I>(&self, reqs: I, options: BulkOptions) -> Res<BulkResponse>
+    where
+        I: IntoIterator<Item = EtcdDeleteRequest>,
+    {
+        let ops = reqs
+            .into_iter()
+            .map(|mut req| {
+                // The deleted keys are waited for the cache to be updated, the same as `delete`
+                if self.kvcache.is_some() {
+                    req.set_prev_kv(true);
+                }
+                (req.key_range(), req.encoded_len(), TxnOp::from(req))
+            })
+            .collect();
+        self.bulk(ops, options).await
+    }
+
+    /// Exports a revision-consistent dump of a key range to `writer`, returns the revision
+    /// the key range is read at.
+    ///
+    /// The dump is in the JSON Lines format: a header line with the revision and the key
+    /// range, an entry line for each key with its base64 encoded key, value and metadata,
+    /// and a trailer line with the number of entries and the CRC32 checksum of the lines.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed or `writer` fails.
+    #[inline]
+    pub async fn export<W>(&self, key_range: KeyRange, writer: &mut W) -> Res<usize>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        dump::export(self, key_range, writer).await
+    }
+
+    /// Imports a dump written by [`Kv::export`] from `reader`. The dump is verified before
+    /// any key is written, then the keys are written in chunked txns as [`Kv::put_many`].
+    /// Every key should be in the key range of the dump and modified no later than its
+    /// revision, and the key range should be under the prefix to rewrite if any.
+    /// The leases in the dump are not restored.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `reader` fails, the dump is invalid, or the txns can't be
+    /// applied atomically in the atomic mode.
+    #[inline]
+    pub async fn import<R>(&self, reader: R, options: ImportOptions) -> Res<BulkResponse>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        dump::import(self, reader, options).await
+    }
+
+    /// Applies the operations, each with its key range and encoded size, in chunked txns.
+    /// The chunks overlapping with earlier chunks are applied after them.
+    async fn bulk(
+        &self,
+        ops: Vec<(KeyRange, usize, TxnOp)>,
+        options: BulkOptions,
+    ) -> Res<BulkResponse> {
+        let sizes = ops
+            .iter()
+            .map(|&(ref key_range, size, _)| (key_range.clone(), size))
+            .collect::<Vec<_>>();
+        let stages = bulk::stages(&sizes, options.split(&sizes)?);
+
+        let mut ops = ops.into_iter();
+        let mut chunks = Vec::new();
+        for stage in stages {
+            let stage = stage
+                .into_iter()
+                .map(|range| {
+                    let chunk_ops = ops.by_ref().take(range.len()).collect::<Vec<_>>();
+                    (range, chunk_ops)
+                })
+                .collect::<Vec<_>>();
+            chunks.extend(
+                futures::stream::iter(stage)
+                    .map(|(range, chunk_ops)| {
+                        self.bulk_chunk(range, chunk_ops, &options.call_options)
+                    })
+                    .buffered(options.concurrency.max(1))
+                    .collect::<Vec<_>>()
+                    .await,
+            );
+        }
+        Ok(BulkResponse::new(chunks))
+    }
+
+    /// Applies a chunk of the bulk operations in a single txn.
+    async fn bulk_chunk(
+        &self,
+        range: Range<usize>,
+        chunk_ops: Vec<(KeyRange, usize, TxnOp)>,
+        call_options: &CallOptions,
+    ) -> BulkChunk {
+        let call_options = call_options.start();
+        let mut txn = EtcdTxnRequest::new();
+        txn.set_call_options(call_options.clone());
+        let mut keys = Vec::with_capacity(chunk_ops.len());
+        for (key_range, _, op) in chunk_ops {
+            // The keys of a delete are known from its response
+            if !matches!(op, TxnOp::Delete(_)) {
+                keys.push(key_range.key);
+            }
+            txn = txn.and_then(op);
+        }
+        let result = async {
+            let mut resp = self.txn(txn).await?;
+            let revision: usize = resp
+                .take_header()
+                .map(|header| header.revision().cast())
+                .ok_or_else(|| EtcdError::InternalError("txn response has no header".to_owned()))?;
+            for mut delete_resp in resp
+                .take_responses()
+                .into_iter()
+                .filter_map(TxnOpResponse::into_delete)
+            {
+                keys.extend(
+                    delete_resp
+                        .take_prev_kvs()
+                        .iter_mut()
+                        .map(EtcdKeyValue::take_key),
+                );
+            }
+            // Wait until cache is updated, the same as the single put and delete
+            for key in &keys {
+                self.wait_cache_updated(key, revision.cast(), call_options.deadline)
+                    .await;
+            }
+            Ok(revision)
+        }
+        .await;
+        BulkChunk::new(range, result)
+    }
+
+    /// Lists the versions of a key modified in the revisions `[from_revision, to_revision]`
+    /// in order, including the deletions. The history is replayed by a watch starting from
+    /// `from_revision`, `to_revision` is capped at the current revision.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed, or `EtcdError::Compacted` if `from_revision`
+    /// is compacted, where the compaction revision is the earliest revision to list from.
+    #[inline]
+    pub async fn history<K>(
+        &self,
+        key: K,
+        from_revision: usize,
+        to_revision: usize,
+    ) -> Res<Vec<KeyVersion>>
+    where
+        K: Into<Vec<u8>>,
+    {
+        self.history_with_options(key, from_revision, to_revision, CallOptions::default())
+            .await
+    }
+
+    /// Lists the versions of a key the same as [`Kv::history`], the deadline of `options`
+    /// bounds the whole replay.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed, `EtcdError::Compacted` if `from_revision`
+    /// is compacted, or `EtcdError::DeadlineExceeded` if the replay doesn't end in time.
+    #[inline]
+    pub async fn history_with_options<K>(
+        &self,
+        key: K,
+        from_revision: usize,
+        to_revision: usize,
+        options: CallOptions,
+    ) -> Res<Vec<KeyVersion>>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let options = options.start();
+        let key = key.into();
+        let from_revision = from_revision.max(1);
+        let read = |revision: usize| {
+            let mut req = EtcdRangeRequest::new(KeyRange::key(key.clone()));
+            req.set_revision(revision);
+            req.set_call_options(options.clone());
+            self.range_rpc(req)
+        };
+        let mut resp = read(0).await?;
+        let current_revision: usize = resp
+            .take_header()
+            .map(|header| header.revision().cast())
+            .ok_or_else(|| EtcdError::InternalError("range response has no header".to_owned()))?;
+        let to_revision = to_revision.min(current_revision);
+        if from_revision > to_revision {
+            return Ok(Vec::new());
+        }
+        // A key not modified since before `from_revision` has no version to list,
+        // and the watch would receive no event to end the replay
+        let kv = if to_revision == current_revision {
+            resp.take_kvs().pop()
+        } else {
+            read(to_revision).await?.take_kvs().pop()
+        };
+        if kv.map_or(false, |kv| kv.mod_revision() < from_revision) {
+            return Ok(Vec::new());
+        }
+        watch::key_history(
+            &self.watch_client,
+            &self.token,
+            key,
+            from_revision,
+            to_revision,
+            &options,
+        )
+        .await
+    }
+
+    /// Performs a compaction operation, the history before the requested revision is removed.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed, or the revision is compacted or in the future.
+    #[inline]
+    pub async fn compact(&self, req: EtcdCompactRequest) -> Res<EtcdCompactResponse> {
+        let options = req.call_options().start();
+        let resp = self
+            .retry_policy
+            .retry(RetryOperation::Compact, options.deadline, || async {
+                let resp = self
+                    .client
+                    .compact_async_opt(&req.clone().into(), self.token.call_option(&options)?)?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
+        Ok(resp)
+    }
+
+    /// Performs an optimistic read-modify-write operation on a key.
+    ///
+    /// `f` is called with the current key-value pair, or `None` if the key doesn't exist,
+    /// and decides how to update the key. The update is committed only if the key is not
+    /// modified since it is read, otherwise the key is read again and `f` is called again.
+    /// The retries follow the retry policy of [`RetryOperation::Update`].
+    /// A put keeps the lease attached to the key, if any.
+    ///
+    /// Returns the revision of the key-value store after the update,
+    /// or the revision of the read for [`UpdateAction::Noop`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed,
+    /// or the key keeps being modified by others after the retries.
+    #[inline]
+    pub async fn update<K, F>(&self, key: K, mut f: F) -> Res<usize>
+    where
+        K: Into<Vec<u8>>,
+        F: FnMut(Option<EtcdKeyValue>) -> UpdateAction,
+    {
+        let key = key.into();
+        let mut backoff =
+            ConflictBackoff::new(self.retry_policy.for_operation(RetryOperation::Update));
+        loop {
+            let mut resp = self
+                .range_rpc(EtcdRangeRequest::new(KeyRange::key(key.clone())))
+                .await?;
+            let read_revision = resp
+                .take_header()
+                .map(|header| header.revision().cast())
+                .ok_or_else(|| {
+                    EtcdError::InternalError("range response has no header".to_owned())
+                })?;
+            let current = resp.take_kvs().pop();
+            let mod_revision = current.as_ref().map_or(0, EtcdKeyValue::mod_revision);
+            let exists = current.is_some();
+
+            let txn = EtcdTxnRequest::new().when_mod_revision(
+                KeyRange::key(key.clone()),
+                TxnCmp::Equal,
+                mod_revision,
+            );
+            let txn = match f(current) {
+                UpdateAction::Put(value) => {
+                    let mut put = EtcdPutRequest::new(key.clone(), value);
+                    // Keep the lease of the existing key, which is unchanged as compared
+                    put.set_ignore_lease(exists);
+                    txn.and_then(put)
+                }
+                UpdateAction::Delete => {
+                    txn.and_then(EtcdDeleteRequest::new(KeyRange::key(key.clone())))
+                }
+                UpdateAction::Noop => return Ok(read_revision),
+            };
+            let mut txn_resp = self.txn(txn).await?;
+            if txn_resp.is_success() {
+                let revision: usize = txn_resp
+                    .take_header()
+                    .map(|header| header.revision().cast())
+                    .ok_or_else(|| {
+                        EtcdError::InternalError("txn response has no header".to_owned())
+                    })?;
+                self.wait_cache_updated(&key, revision.cast(), None).await;
+                return Ok(revision);
+            }
+            backoff.conflicted().await?;
+        }
+    }
+
+    /// Runs `f` in a software transaction with the isolation level, and commits the writes
+    /// made through the `StmTxn` in a single transaction. `f` is run again with a new
+    /// `StmTxn` if the commit conflicts with other writes, the retries follow the retry
+    /// policy of [`RetryOperation::Stm`].
+    ///
+    /// ```ignore
+    /// // Swap the values of two keys atomically
+    /// client
+    ///     .kv()
+    ///     .stm(Isolation::Serializable, |txn| {
+    ///         Box::pin(async move {
+    ///             let a = txn.get("a").await?.unwrap_or_default();
+    ///             let b = txn.get("b").await?.unwrap_or_default();
+    ///             txn.put("a", b);
+    ///             txn.put("b", a);
+    ///             Ok(())
+    ///         })
+    ///     })
+    ///     .await?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed, `f` returns an error,
+    /// or the transaction keeps conflicting after the retries. The commit is not retried
+    /// if its compares or writes exceed the default `--max-txn-ops` of etcd (128), which
+    /// returns [`EtcdError::TxnOpsExceeded`].
+    #[inline]
+    pub async fn stm<'k, T, F>(&'k self, isolation: Isolation, f: F) -> Res<T>
+    where
+        F: for<'s> FnMut(&'s mut StmTxn<'k>) -> StmFuture<'s, T>,
+    {
+        let policy = self.retry_policy.for_operation(RetryOperation::Stm);
+        stm::run(self, policy, isolation, f).await
+    }
+
+    /// Compacts the history periodically, checks the retention every `interval`.
+    ///
+    /// It runs until the returned future is dropped and never completes by itself.
+    /// Only one process of the cluster should run it, such as the one holding a lock.
+    #[inline]
+    pub async fn auto_compact(&self, retention: CompactionRetention, interval: Duration) {
+        compact::run_auto_compaction(self, retention, interval).await;
+    }
+
+    /// Gets the current revision of the key-value store.
+    pub(crate) async fn current_revision(&self) -> Res<usize> {
+        let mut req = EtcdRangeRequest::new(KeyRange::key(vec![0]));
+        req.set_count_only(true);
+        let mut resp = self.range_rpc(req).await?;
+        resp.take_header()
+            .map(|header| header.revision().cast())
+            .ok_or_else(|| EtcdError::InternalError("range response has no header".to_owned()))
+    }
+
+    /// Waits until the cache reflects a write of `key` at `revision`, that is the key is
+    /// not cached or its cached revision, present or absent, is no less than `revision`.
+    ///
+    /// The wait ends early at `deadline`, or when the watches of the cache are recovering,
+    /// as the cache is not read until they are created again.
+    pub(crate) async fn wait_cache_updated(
+        &self,
+        key: &[u8],
+        revision: i64,
+        deadline: Option<Instant>,
+    ) {
+        if let Some(ref kvcache) = self.kvcache {
+            let waiting = || {
+                kvcache.load().is_watching()
+                    && deadline.map_or(true, |deadline| Instant::now() < deadline)
+            };
+            while let Some(cached_revision) = kvcache.load().cache.revision(key) {
+                if cached_revision >= revision || !waiting() {
+                    return;
+                }
+                Timer::after(Duration::from_millis(1)).await;
+            }
+            // The cached key ranges containing the key should also reach the revision
+            while let Some(range_revision) = kvcache.load().ranges.revision_of(key).await {
+                if range_revision >= revision || !waiting() {
+                    return;
+                }
+                Timer::after(Duration::from_millis(1)).await;
+            }
+        }
+    }
+
     /// Shut down the running watch task, if any.
     /// This should only be called when there are
     /// no other threads accessing the cache.
@@ -547,11 +1471,25 @@ impl Kv {
     }
 }
 
+/// The update decided by the function of [`Kv::update`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateAction {
+    /// Puts the value to the key.
+    Put(Vec<u8>),
+    /// Deletes the key.
+    Delete,
+    /// Leaves the key unchanged.
+    Noop,
+}
+
 /// Watch request struct
 #[derive(Debug, Clone)]
 pub struct LocalWatchRequest {
     /// Request key
     key: Vec<u8>,
+    /// Request range end, empty if the request is for a single key
+    range_end: Vec<u8>,
     /// Revision to start watch
     /// Set to -1 if the request is to cancel
     revision: i64,
@@ -564,6 +1502,7 @@ impl LocalWatchRequest {
     fn create(key: Vec<u8>, revision: i64) -> Self {
         Self {
             key,
+            range_end: vec![],
             revision,
             is_create: true,
         }
@@ -573,10 +1512,48 @@ impl LocalWatchRequest {
     fn cancel(key: Vec<u8>) -> Self {
         Self {
             key,
+            range_end: vec![],
             revision: -1,
             is_create: false,
         }
     }
+
+    /// Create watch request of a cached key range
+    fn create_range(key_range: KeyRange, revision: i64) -> Self {
+        Self {
+            key: key_range.key,
+            range_end: key_range.range_end,
+            revision,
+            is_create: true,
+        }
+    }
+
+    /// Cancel watch request of a cached key range
+    fn cancel_range(key_range: KeyRange) -> Self {
+        Self {
+            key: key_range.key,
+            range_end: key_range.range_end,
+            revision: -1,
+            is_create: false,
+        }
+    }
+
+    /// Gets the key and range end identifying the watch
+    fn watch_key(&self) -> (Vec<u8>, Vec<u8>) {
+        (self.key.clone(), self.range_end.clone())
+    }
+
+    /// Advances the revision to resume the watch from by a response of the watch, to the
+    /// revision following the last event, or the revision of a progress notification.
+    /// The revision of the created response is not used, as the events up to it may be
+    /// still on the way if the watch starts from an earlier revision.
+    fn advance(&mut self, resp: &WatchResponse) {
+        let seen_revision = resp.get_events().last().map_or_else(
+            || resp.get_header().get_revision(),
+            |event| event.get_kv().get_mod_revision(),
+        );
+        self.revision = self.revision.max(seen_revision.overflow_add(1));
+    }
 }
 
 /// Key-Value pair.
@@ -653,9 +1630,10 @@ impl EtcdKeyValue {
         self.proto.version.cast()
     }
 
-    /// Gets the ID of the lease that attached to key.
+    /// Gets the ID of the lease that attached to key, the same type as the lease IDs of
+    /// the lease, put and txn requests.
     #[inline]
-    pub fn lease(&self) -> usize {
+    pub fn lease(&self) -> u64 {
         self.proto.lease.cast()
     }
 
@@ -747,24 +1725,16 @@ impl KeyRange {
             return Self::all();
         }
 
-        let mut first_value = true;
-        let mut range_end = key
-            .iter()
-            .rev()
-            .filter_map(|e| {
-                if *e < 0xff {
-                    if first_value {
-                        first_value = false;
-                        Some(e.overflow_add(1))
-                    } else {
-                        Some(*e)
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect::<Vec<u8>>();
-        range_end.reverse();
+        // The end is the prefix with the trailing 0xff bytes trimmed and the last byte
+        // incremented, or `\0` for all the keys no less than the prefix if it is all 0xff
+        let mut range_end = key.clone();
+        while range_end.last() == Some(&0xff) {
+            range_end.pop();
+        }
+        match range_end.last_mut() {
+            Some(last) => *last = last.overflow_add(1),
+            None => range_end.push(0),
+        }
         Self { key, range_end }
     }
 
@@ -779,4 +1749,95 @@ impl KeyRange {
     pub fn take_range_end(&mut self) -> Vec<u8> {
         std::mem::take(&mut self.range_end)
     }
+
+    /// Returns `true` if the key range shares any key with `other`.
+    pub(crate) fn overlaps(&self, other: &Self) -> bool {
+        self.starts_before_end_of(other) && other.starts_before_end_of(self)
+    }
+
+    /// Returns `true` if the key range starts before the end of `other`.
+    fn starts_before_end_of(&self, other: &Self) -> bool {
+        match *other.range_end {
+            [] => self.key <= other.key,
+            [0] => true,
+            ref range_end => self.key.as_slice() < range_end,
+        }
+    }
+
+    /// Returns `true` if the key is in the range.
+    pub(crate) fn contains(&self, key: &[u8]) -> bool {
+        match *self.range_end {
+            [] => key == self.key.as_slice(),
+            [0] => key >= self.key.as_slice(),
+            ref range_end => key >= self.key.as_slice() && key < range_end,
+        }
+    }
+}
+
+#[allow(clippy::indexing_slicing)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos::kv::Event;
+
+    /// Creates a watch response with the events modified at the revisions.
+    fn watch_response(header_revision: i64, event_revisions: &[i64]) -> WatchResponse {
+        let mut resp = WatchResponse::new();
+        resp.mut_header().set_revision(header_revision);
+        let events = event_revisions
+            .iter()
+            .map(|&revision| {
+                let mut event = Event::new();
+                event.mut_kv().set_mod_revision(revision);
+                event
+            })
+            .collect();
+        resp.set_events(RepeatedField::from_vec(events));
+        resp
+    }
+
+    #[test]
+    fn test_resume_unchanged_key() {
+        let cache = Cache::new(CacheCapacity::Entries(16), Box::new(LruPolicy::new()), 16);
+        let mut kv = KeyValue::new();
+        kv.set_key(b"key".to_vec());
+        kv.set_mod_revision(4);
+        cache.insert_or_update(b"key".to_vec(), kv);
+
+        let mut req = LocalWatchRequest::create(b"key".to_vec(), 5);
+        req.advance(&watch_response(8, &[]));
+        assert_eq!(
+            req.revision, 9,
+            "A progress notification should advance the watch"
+        );
+        req.advance(&watch_response(20, &[6, 7]));
+        assert_eq!(
+            req.revision, 9,
+            "The events before the revision seen should not move the watch back"
+        );
+        req.advance(&watch_response(20, &[]));
+
+        // The key has no change while other keys are modified and compacted up to 15
+        let mut watch_map = HashMap::new();
+        watch_map.insert(req.watch_key(), 1);
+        let mut watch_ids = HashMap::new();
+        watch_ids.insert(1, req);
+        let mut watch_request_queue = VecDeque::new();
+        KvCache::resume_watches(
+            &cache,
+            &mut watch_map,
+            &mut watch_ids,
+            &mut watch_request_queue,
+            None,
+        );
+        assert_eq!(watch_request_queue.len(), 1, "The watch should be resumed");
+        assert!(
+            watch_request_queue[0].revision > 15,
+            "The resumed watch should start after the compacted revision"
+        );
+        assert!(
+            cache.search(b"key").is_some(),
+            "The unchanged key should stay in cache"
+        );
+    }
 }
@@ -1,13 +1,18 @@
+use crate::namespace;
 use crate::proto::etcdserverpb::{PutRequest, PutResponse};
+use crate::CallOptions;
 use crate::EtcdKeyValue;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
+use prost::Message;
 
 /// Request for putting key-value.
 #[derive(Debug, Clone)]
 pub struct EtcdPutRequest {
     /// Etcd put key-value pairs request.
     proto: PutRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdPutRequest {
@@ -27,7 +32,10 @@ impl EtcdPutRequest {
             ignore_lease: false,
             ..PutRequest::default()
         };
-        Self { proto: put_request }
+        Self {
+            proto: put_request,
+            options: CallOptions::default(),
+        }
     }
 
     /// Sets the lease ID to associate with the key in the key-value store.
@@ -66,6 +74,28 @@ impl EtcdPutRequest {
     pub fn get_value(&self) -> Vec<u8> {
         self.proto.value
     }
+
+    /// Gets the encoded size of the request.
+    pub(super) fn encoded_len(&self) -> usize {
+        Message::encoded_len(&self.proto)
+    }
+
+    /// Prepends the namespace prefix to the keys of the request.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        namespace::prefix_key(prefix, &mut self.proto.key);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdPutRequest> for PutRequest {
@@ -100,6 +130,13 @@ impl EtcdPutResponse {
     pub fn get_revision(&self) -> i64 {
         self.proto.header.unwrap().revision
     }
+
+    /// Strips the namespace prefix from the keys of the response.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        if let Some(kv) = self.proto.prev_kv.as_mut() {
+            namespace::strip_key(prefix, &mut kv.key);
+        }
+    }
 }
 
 impl From<PutResponse> for EtcdPutResponse {
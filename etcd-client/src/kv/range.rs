@@ -1,12 +1,16 @@
-use super::{EtcdKeyValue, KeyRange};
-use crate::proto::etcdserverpb::{RangeRequest, RangeResponse};
+use super::{EtcdGetRequest, EtcdKeyValue, KeyRange};
+use crate::namespace;
+use crate::proto::etcdserverpb::{range_request, RangeRequest, RangeResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
 
 /// Request for fetching key-value pairs.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EtcdRangeRequest {
     proto: RangeRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdRangeRequest {
@@ -30,6 +34,7 @@ impl EtcdRangeRequest {
                 min_create_revision: 0,
                 max_create_revision: 0,
             },
+            options: CallOptions::default(),
         }
     }
 
@@ -40,6 +45,68 @@ impl EtcdRangeRequest {
         self.proto.limit = limit.cast();
     }
 
+    /// Sets the revision of the point-in-time key-value store to read.
+    /// When revision is set to 0, the latest revision is read.
+    #[inline]
+    pub fn set_revision(&mut self, revision: usize) {
+        self.proto.revision = revision.cast();
+    }
+
+    /// Sets the order of the returned keys.
+    #[inline]
+    pub fn set_sort_order(&mut self, sort_order: SortOrder) {
+        self.proto.sort_order = range_request::SortOrder::from(sort_order).into();
+    }
+
+    /// Sets the field to sort the returned keys by.
+    #[inline]
+    pub fn set_sort_target(&mut self, sort_target: SortTarget) {
+        self.proto.sort_target = range_request::SortTarget::from(sort_target).into();
+    }
+
+    /// When set, the request is served by the local member without going through consensus,
+    /// which is faster but may return stale data.
+    #[inline]
+    pub fn set_serializable(&mut self, serializable: bool) {
+        self.proto.serializable = serializable;
+    }
+
+    /// When set, only the keys are returned without values.
+    #[inline]
+    pub fn set_keys_only(&mut self, keys_only: bool) {
+        self.proto.keys_only = keys_only;
+    }
+
+    /// When set, only the count of the keys is returned.
+    #[inline]
+    pub fn set_count_only(&mut self, count_only: bool) {
+        self.proto.count_only = count_only;
+    }
+
+    /// Filters out the keys whose mod revision is less than `revision`.
+    #[inline]
+    pub fn set_min_mod_revision(&mut self, revision: usize) {
+        self.proto.min_mod_revision = revision.cast();
+    }
+
+    /// Filters out the keys whose mod revision is greater than `revision`.
+    #[inline]
+    pub fn set_max_mod_revision(&mut self, revision: usize) {
+        self.proto.max_mod_revision = revision.cast();
+    }
+
+    /// Filters out the keys whose create revision is less than `revision`.
+    #[inline]
+    pub fn set_min_create_revision(&mut self, revision: usize) {
+        self.proto.min_create_revision = revision.cast();
+    }
+
+    /// Filters out the keys whose create revision is greater than `revision`.
+    #[inline]
+    pub fn set_max_create_revision(&mut self, revision: usize) {
+        self.proto.max_create_revision = revision.cast();
+    }
+
     /// Gets the `key_range` from the `RangeRequest`.
     #[inline]
     pub fn get_key_range(&self) -> KeyRange {
@@ -54,6 +121,28 @@ impl EtcdRangeRequest {
     pub fn is_single_key(&self) -> bool {
         self.proto.range_end.is_empty()
     }
+
+    /// Converts a single key request into a get request, keeping all the options.
+    pub(super) fn into_get_request(self) -> EtcdGetRequest {
+        EtcdGetRequest::from_parts(self.proto, self.options)
+    }
+
+    /// Prepends the namespace prefix to the keys of the request.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        namespace::prefix_key_range(prefix, &mut self.proto.key, &mut self.proto.range_end);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdRangeRequest> for RangeRequest {
@@ -63,6 +152,58 @@ impl From<EtcdRangeRequest> for RangeRequest {
     }
 }
 
+/// The order of the keys in a range response.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// No sorting, the keys are in lexical order.
+    None,
+    /// Ascending order.
+    Ascend,
+    /// Descending order.
+    Descend,
+}
+
+impl From<SortOrder> for range_request::SortOrder {
+    #[inline]
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::None => Self::None,
+            SortOrder::Ascend => Self::Ascend,
+            SortOrder::Descend => Self::Descend,
+        }
+    }
+}
+
+/// The field to sort the keys of a range response by.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortTarget {
+    /// Sort by key.
+    Key,
+    /// Sort by version.
+    Version,
+    /// Sort by create revision.
+    Create,
+    /// Sort by mod revision.
+    Mod,
+    /// Sort by value.
+    Value,
+}
+
+impl From<SortTarget> for range_request::SortTarget {
+    #[inline]
+    fn from(target: SortTarget) -> Self {
+        match target {
+            SortTarget::Key => Self::Key,
+            SortTarget::Version => Self::Version,
+            SortTarget::Create => Self::Create,
+            SortTarget::Mod => Self::Mod,
+            SortTarget::Value => Self::Value,
+        }
+    }
+}
+
 /// Response for `RangeRequest`.
 #[derive(Debug)]
 pub struct EtcdRangeResponse {
@@ -108,6 +249,13 @@ impl EtcdRangeResponse {
     pub fn get_kvs(&self) -> Vec<EtcdKeyValue> {
         self.proto.kvs.clone().into_iter().map(From::from).collect()
     }
+
+    /// Strips the namespace prefix from the keys of the response.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        for kv in &mut self.proto.kvs {
+            namespace::strip_key(prefix, &mut kv.key);
+        }
+    }
 }
 
 impl From<RangeResponse> for EtcdRangeResponse {
@@ -0,0 +1,203 @@
+//! The implementation for caching key ranges.
+use super::KeyRange;
+use super::KeyValue;
+use crate::protos::kv::{Event, Event_EventType};
+use crate::protos::rpc::{
+    RangeRequest, RangeRequest_SortOrder, RangeRequest_SortTarget, RangeResponse, ResponseHeader,
+};
+use clippy_utilities::Cast;
+use protobuf::RepeatedField;
+use smol::lock::RwLock;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// A key range cached as a whole.
+struct CachedRange {
+    /// The cached key range.
+    key_range: KeyRange,
+    /// The revision the key range is up to date with, 0 if the key range is not loaded yet.
+    revision: i64,
+    /// The key-value pairs in the key range, ordered by key.
+    kvs: BTreeMap<Vec<u8>, KeyValue>,
+}
+
+impl CachedRange {
+    /// Returns `true` if the key range covers all the keys of the request.
+    fn covers(&self, key: &[u8], range_end: &[u8]) -> bool {
+        let KeyRange {
+            key: ref start,
+            range_end: ref end,
+        } = self.key_range;
+        if key < start.as_slice() {
+            return false;
+        }
+        match (end.as_slice(), range_end) {
+            ([0], _) => true,
+            (_, []) => key < end.as_slice(),
+            (_, [0]) => false,
+            (end, range_end) => range_end <= end,
+        }
+    }
+}
+
+/// Cache of the registered key ranges, each kept fresh by a range watch.
+///
+/// Unlike the keys in `Cache`, a cached key range is never evicted,
+/// so it can answer any read within the range, including the keys that don't exist.
+pub struct RangeCache {
+    /// The registered key ranges.
+    ranges: RwLock<Vec<CachedRange>>,
+}
+
+impl RangeCache {
+    /// Create a new empty `RangeCache`.
+    pub fn new() -> Self {
+        Self {
+            ranges: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Registers a key range, returns `false` if it is already registered.
+    pub async fn register(&self, key_range: KeyRange) -> bool {
+        let mut ranges = self.ranges.write().await;
+        if ranges.iter().any(|range| range.key_range == key_range) {
+            return false;
+        }
+        ranges.push(CachedRange {
+            key_range,
+            revision: 0,
+            kvs: BTreeMap::new(),
+        });
+        true
+    }
+
+    /// Unregisters a key range, returns `false` if it is not registered.
+    pub async fn unregister(&self, key_range: &KeyRange) -> bool {
+        let mut ranges = self.ranges.write().await;
+        let len = ranges.len();
+        ranges.retain(|range| range.key_range != *key_range);
+        ranges.len() != len
+    }
+
+    /// Loads a registered key range with its key-value pairs read at `revision`.
+    pub async fn load(&self, key_range: &KeyRange, kvs: Vec<KeyValue>, revision: i64) {
+        let mut ranges = self.ranges.write().await;
+        if let Some(range) = ranges
+            .iter_mut()
+            .find(|range| range.key_range == *key_range)
+        {
+            range.kvs = kvs
+                .into_iter()
+                .map(|kv| (kv.get_key().to_vec(), kv))
+                .collect();
+            range.revision = revision;
+        }
+    }
+
+    /// Applies the watch events of a key range, `revision` is the revision of the watch
+    /// response, which may have no event if it is a progress notification.
+    pub async fn apply(&self, key_range: &KeyRange, events: &[Event], revision: i64) {
+        let mut ranges = self.ranges.write().await;
+        if let Some(range) = ranges
+            .iter_mut()
+            .find(|range| range.key_range == *key_range)
+        {
+            for event in events {
+                let kv = event.get_kv();
+                if event.get_field_type() == Event_EventType::PUT {
+                    let _prev = range.kvs.insert(kv.get_key().to_vec(), kv.clone());
+                } else {
+                    let _prev = range.kvs.remove(kv.get_key());
+                }
+            }
+            range.revision = range.revision.max(revision);
+        }
+    }
+
+    /// Gets the revision a loaded key range containing `key` is up to date with,
+    /// the smallest one if several key ranges contain the key.
+    pub async fn revision_of(&self, key: &[u8]) -> Option<i64> {
+        self.ranges
+            .read()
+            .await
+            .iter()
+            .filter(|range| range.revision > 0 && range.key_range.contains(key))
+            .map(|range| range.revision)
+            .min()
+    }
+
+    /// Answers a range request from a loaded key range covering it. Returns `None` if no
+    /// key range covers the request, or the request reads a past revision or filters the
+    /// keys by revisions.
+    pub async fn search(&self, req: &RangeRequest) -> Option<RangeResponse> {
+        if req.get_revision() != 0
+            || req.get_min_mod_revision() != 0
+            || req.get_max_mod_revision() != 0
+            || req.get_min_create_revision() != 0
+            || req.get_max_create_revision() != 0
+        {
+            return None;
+        }
+        let ranges = self.ranges.read().await;
+        let range = ranges
+            .iter()
+            .find(|range| range.revision > 0 && range.covers(req.get_key(), req.get_range_end()))?;
+
+        let key = req.get_key().to_vec();
+        let upper = match *req.get_range_end() {
+            [] => Bound::Included(key.clone()),
+            [0] => Bound::Unbounded,
+            ref range_end => Bound::Excluded(range_end.to_vec()),
+        };
+        let mut kvs = range
+            .kvs
+            .range((Bound::Included(key), upper))
+            .map(|(_, kv)| kv.clone())
+            .collect::<Vec<_>>();
+
+        let mut response = RangeResponse::new();
+        let mut header = ResponseHeader::new();
+        header.set_revision(range.revision);
+        response.set_header(header);
+        response.set_count(kvs.len().cast());
+        if req.get_count_only() {
+            return Some(response);
+        }
+
+        // The same as etcd, sorting by a field other than the key is ascending by default
+        let sort_order = match (req.get_sort_order(), req.get_sort_target()) {
+            (RangeRequest_SortOrder::NONE, RangeRequest_SortTarget::KEY) => {
+                RangeRequest_SortOrder::NONE
+            }
+            (RangeRequest_SortOrder::NONE, _) => RangeRequest_SortOrder::ASCEND,
+            (sort_order, _) => sort_order,
+        };
+        if sort_order != RangeRequest_SortOrder::NONE {
+            match req.get_sort_target() {
+                RangeRequest_SortTarget::KEY => {}
+                RangeRequest_SortTarget::VERSION => kvs.sort_by_key(KeyValue::get_version),
+                RangeRequest_SortTarget::CREATE => kvs.sort_by_key(KeyValue::get_create_revision),
+                RangeRequest_SortTarget::MOD => kvs.sort_by_key(KeyValue::get_mod_revision),
+                RangeRequest_SortTarget::VALUE => {
+                    kvs.sort_by(|a, b| a.get_value().cmp(b.get_value()));
+                }
+            }
+            if sort_order == RangeRequest_SortOrder::DESCEND {
+                kvs.reverse();
+            }
+        }
+
+        let limit: usize = req.get_limit().cast();
+        if limit > 0 && kvs.len() > limit {
+            kvs.truncate(limit);
+            response.set_more(true);
+        }
+        if req.get_keys_only() {
+            for kv in &mut kvs {
+                kv.clear_value();
+            }
+        }
+        response.set_kvs(RepeatedField::from_vec(kvs));
+        Some(response)
+    }
+}
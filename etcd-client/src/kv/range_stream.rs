@@ -0,0 +1,78 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_stream::try_stream;
+use clippy_utilities::Cast;
+use futures::Stream;
+
+use super::{EtcdKeyValue, EtcdRangeRequest, KeyRange, Kv};
+use crate::Result;
+
+/// A stream of the key-value pairs in a key range, which are fetched page by page.
+///
+/// All the pages are read at the revision of the first page,
+/// so the key-value pairs are a consistent snapshot of the range.
+pub struct RangeStream<'a> {
+    /// The key-value pairs of the pages.
+    inner: Pin<Box<dyn Stream<Item = Result<EtcdKeyValue>> + Send + 'a>>,
+    /// The revision which the pages are pinned to, 0 before the first page is fetched.
+    revision: Arc<AtomicUsize>,
+}
+
+impl<'a> RangeStream<'a> {
+    /// Creates a new `RangeStream`, reads the latest revision if `revision` is 0.
+    pub(super) fn new(kv: &'a Kv, key_range: KeyRange, page_size: usize, revision: usize) -> Self {
+        let pinned_revision = Arc::new(AtomicUsize::new(revision));
+        let stream_revision = Arc::clone(&pinned_revision);
+        let inner = try_stream! {
+            let KeyRange { mut key, range_end } = key_range;
+            loop {
+                let mut req = EtcdRangeRequest::new(KeyRange::range(key.clone(), range_end.clone()));
+                req.set_limit(page_size);
+                req.set_revision(stream_revision.load(Ordering::Acquire));
+                let mut resp = kv.range_rpc(req).await?;
+                // Only the first page sets the revision, the others are read at it
+                if stream_revision.load(Ordering::Acquire) == 0 {
+                    if let Some(header) = resp.take_header() {
+                        stream_revision.store(header.revision().cast(), Ordering::Release);
+                    }
+                }
+                let has_more = resp.has_more();
+                for key_value in resp.take_kvs() {
+                    // The next page starts from the key following the last key
+                    key = key_value.key().to_vec();
+                    key.push(0);
+                    yield key_value;
+                }
+                if !has_more {
+                    break;
+                }
+            }
+        };
+        Self {
+            inner: Box::pin(inner),
+            revision: pinned_revision,
+        }
+    }
+
+    /// Returns the revision of the snapshot, `None` before the first page is fetched.
+    #[inline]
+    #[must_use]
+    pub fn revision(&self) -> Option<usize> {
+        match self.revision.load(Ordering::Acquire) {
+            0 => None,
+            revision => Some(revision),
+        }
+    }
+}
+
+impl Stream for RangeStream<'_> {
+    type Item = Result<EtcdKeyValue>;
+
+    #[inline]
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
@@ -2,7 +2,10 @@ use super::{
     EtcdDeleteRequest, EtcdDeleteResponse, EtcdPutRequest, EtcdPutResponse, EtcdRangeRequest,
     EtcdRangeResponse, KeyRange,
 };
+use crate::namespace;
 use crate::proto::etcdserverpb::{Compare, RequestOp, ResponseOp, TxnRequest, TxnResponse};
+use crate::protos::rpc::{RequestOp_oneof_request, ResponseOp_oneof_response};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
 
@@ -11,6 +14,8 @@ use clippy_utilities::Cast;
 pub struct EtcdTxnRequest {
     /// Etcd transaction operations request.
     proto: TxnRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdTxnRequest {
@@ -24,7 +29,10 @@ impl EtcdTxnRequest {
             failure: RepeatedField::from_vec(vec![]),
             ..TxnRequest::default()
         };
-        Self { proto: txn_request }
+        Self {
+            proto: txn_request,
+            options: CallOptions::default(),
+        }
     }
 
     /// Adds a version compare.
@@ -103,6 +111,39 @@ impl EtcdTxnRequest {
         self
     }
 
+    /// Adds a lease compare, a lease ID of 0 means the key has no lease.
+    #[inline]
+    #[must_use]
+    pub fn when_lease(mut self, key_range: KeyRange, cmp: TxnCmp, lease: u64) -> Self {
+        let compare_result: Compare_CompareResult = cmp.into();
+        let compare = Compare {
+            result: compare_result,
+            target: Compare_CompareTarget::LEASE,
+            key: key_range.key,
+            range_end: key_range.range_end,
+            target_union: Some(Compare_oneof_target_union::lease(lease.cast())),
+            ..Compare::default()
+        };
+        self.proto.compare.push(compare);
+        self
+    }
+
+    /// Adds a compare which succeeds if the keys exist,
+    /// that is the create revision is greater than 0.
+    #[inline]
+    #[must_use]
+    pub fn when_exists(self, key_range: KeyRange) -> Self {
+        self.when_create_revision(key_range, TxnCmp::Greater, 0)
+    }
+
+    /// Adds a compare which succeeds if the keys don't exist,
+    /// that is the create revision is 0.
+    #[inline]
+    #[must_use]
+    pub fn when_not_exists(self, key_range: KeyRange) -> Self {
+        self.when_create_revision(key_range, TxnCmp::Equal, 0)
+    }
+
     /// If compare success, then execute the specified operations.
     #[inline]
     #[must_use]
@@ -136,6 +177,23 @@ impl EtcdTxnRequest {
     pub fn get_failure_operations(&self) -> Vec<RequestOp> {
         self.proto.failure.to_vec()
     }
+
+    /// Prepends the namespace prefix to the keys of the compares and the operations.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        prefix_txn_keys(&mut self.proto, prefix);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl Default for EtcdTxnRequest {
@@ -145,6 +203,30 @@ impl Default for EtcdTxnRequest {
     }
 }
 
+/// Prepends the namespace prefix to the keys of a txn, including the nested txns.
+fn prefix_txn_keys(txn: &mut TxnRequest, prefix: &[u8]) {
+    for compare in &mut txn.compare {
+        namespace::prefix_key_range(prefix, &mut compare.key, &mut compare.range_end);
+    }
+    for op in txn.success.iter_mut().chain(txn.failure.iter_mut()) {
+        match op.request {
+            Some(RequestOp_oneof_request::request_range(ref mut req)) => {
+                namespace::prefix_key_range(prefix, &mut req.key, &mut req.range_end);
+            }
+            Some(RequestOp_oneof_request::request_put(ref mut req)) => {
+                namespace::prefix_key(prefix, &mut req.key);
+            }
+            Some(RequestOp_oneof_request::request_delete_range(ref mut req)) => {
+                namespace::prefix_key_range(prefix, &mut req.key, &mut req.range_end);
+            }
+            Some(RequestOp_oneof_request::request_txn(ref mut req)) => {
+                prefix_txn_keys(req, prefix);
+            }
+            None => {}
+        }
+    }
+}
+
 impl From<EtcdTxnRequest> for TxnRequest {
     #[inline]
     fn from(e: EtcdTxnRequest) -> Self {
@@ -240,6 +322,88 @@ pub enum TxnOpResponse {
     Txn(EtcdTxnResponse),
 }
 
+impl TxnOpResponse {
+    /// Returns the range response if the operation is a range.
+    #[inline]
+    #[must_use]
+    pub const fn as_range(&self) -> Option<&EtcdRangeResponse> {
+        match *self {
+            Self::Range(ref resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Returns the put response if the operation is a put.
+    #[inline]
+    #[must_use]
+    pub const fn as_put(&self) -> Option<&EtcdPutResponse> {
+        match *self {
+            Self::Put(ref resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Returns the delete response if the operation is a delete.
+    #[inline]
+    #[must_use]
+    pub const fn as_delete(&self) -> Option<&EtcdDeleteResponse> {
+        match *self {
+            Self::Delete(ref resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Returns the transaction response if the operation is a nested transaction.
+    #[inline]
+    #[must_use]
+    pub const fn as_txn(&self) -> Option<&EtcdTxnResponse> {
+        match *self {
+            Self::Txn(ref resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Converts into the range response if the operation is a range.
+    #[inline]
+    #[must_use]
+    pub fn into_range(self) -> Option<EtcdRangeResponse> {
+        match self {
+            Self::Range(resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Converts into the put response if the operation is a put.
+    #[inline]
+    #[must_use]
+    pub fn into_put(self) -> Option<EtcdPutResponse> {
+        match self {
+            Self::Put(resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Converts into the delete response if the operation is a delete.
+    #[inline]
+    #[must_use]
+    pub fn into_delete(self) -> Option<EtcdDeleteResponse> {
+        match self {
+            Self::Delete(resp) => Some(resp),
+            _ => None,
+        }
+    }
+
+    /// Converts into the transaction response if the operation is a nested transaction.
+    #[inline]
+    #[must_use]
+    pub fn into_txn(self) -> Option<EtcdTxnResponse> {
+        match self {
+            Self::Txn(resp) => Some(resp),
+            _ => None,
+        }
+    }
+}
+
 impl From<ResponseOp> for TxnOpResponse {
     #[inline]
     fn from(mut resp: ResponseOp) -> Self {
@@ -296,6 +460,38 @@ impl EtcdTxnResponse {
             .map(From::from)
             .collect()
     }
+
+    /// Strips the namespace prefix from the keys of the responses.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        strip_txn_key_prefix(&mut self.proto, prefix);
+    }
+}
+
+/// Strips the namespace prefix from the keys of a txn response, including the nested txns.
+fn strip_txn_key_prefix(txn: &mut TxnResponse, prefix: &[u8]) {
+    for op in &mut txn.responses {
+        match op.response {
+            Some(ResponseOp_oneof_response::response_range(ref mut resp)) => {
+                for kv in &mut resp.kvs {
+                    namespace::strip_key(prefix, &mut kv.key);
+                }
+            }
+            Some(ResponseOp_oneof_response::response_put(ref mut resp)) => {
+                if let Some(kv) = resp.prev_kv.as_mut() {
+                    namespace::strip_key(prefix, &mut kv.key);
+                }
+            }
+            Some(ResponseOp_oneof_response::response_delete_range(ref mut resp)) => {
+                for kv in &mut resp.prev_kvs {
+                    namespace::strip_key(prefix, &mut kv.key);
+                }
+            }
+            Some(ResponseOp_oneof_response::response_txn(ref mut resp)) => {
+                strip_txn_key_prefix(resp, prefix);
+            }
+            None => {}
+        }
+    }
 }
 
 impl From<TxnResponse> for EtcdTxnResponse {
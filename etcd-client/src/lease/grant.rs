@@ -1,6 +1,7 @@
 use std::time::Duration;
 
 use crate::protos::rpc::{LeaseGrantRequest, LeaseGrantResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
 
@@ -9,6 +10,8 @@ use clippy_utilities::Cast;
 pub struct EtcdLeaseGrantRequest {
     /// Etcd lease grant request.
     proto: LeaseGrantRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdLeaseGrantRequest {
@@ -22,7 +25,10 @@ impl EtcdLeaseGrantRequest {
             ..LeaseGrantRequest::default()
         };
 
-        Self { proto }
+        Self {
+            proto,
+            options: CallOptions::default(),
+        }
     }
 
     /// Set custom lease ID.
@@ -30,6 +36,18 @@ impl EtcdLeaseGrantRequest {
     pub fn set_id(&mut self, id: u64) {
         self.proto.ID = id.cast();
     }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdLeaseGrantRequest> for LeaseGrantRequest {
@@ -53,6 +53,7 @@
 //! }
 //! ```
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use async_trait::async_trait;
@@ -62,27 +63,22 @@ use futures::stream::StreamExt;
 use futures::prelude::*;
 
 use smol::channel::{unbounded, Receiver, Sender};
+use smol::lock::Mutex;
 use smol::stream::Stream;
 
 pub use grant::{EtcdLeaseGrantRequest, EtcdLeaseGrantResponse};
 pub use keep_alive::{EtcdLeaseKeepAliveRequest, EtcdLeaseKeepAliveResponse};
 pub use revoke::{EtcdLeaseRevokeRequest, EtcdLeaseRevokeResponse};
 
-use crate::lazy::{Lazy, Shutdown};
+use crate::auth::AuthToken;
+use crate::lazy::Shutdown;
 use crate::protos::rpc;
 use crate::protos::rpc_grpc::LeaseClient;
-use crate::retryable;
 use crate::Result;
-use crate::CURRENT_INTERVAL_ENV_KEY;
-use crate::CURRENT_INTERVAL_VALUE;
-use crate::INITIAL_INTERVAL_ENV_KEY;
-use crate::INITIAL_INTERVAL_VALUE;
-use crate::MAX_ELAPSED_TIME_ENV_KEY;
-use crate::MAX_ELAPSED_TIME_VALUE;
-use backoff::ExponentialBackoff;
-use std::time::Duration;
+use crate::{CallOptions, RetryOperation, RetryPolicy};
 
 use grpcio::WriteFlags;
+use log::warn;
 
 /// Grant mod for granting lease operations.
 mod grant;
@@ -96,86 +92,91 @@ mod revoke;
 struct LeaseKeepAliveTunnel {
     /// A channel sender to send keep alive request.
     req_sender: Sender<EtcdLeaseKeepAliveRequest>,
-    /// A channel receiver to receive keep alive response.
-    resp_receiver: Option<Receiver<Result<EtcdLeaseKeepAliveResponse>>>,
     /// A channel sender to send shutdown request.
     shutdown: Option<Sender<()>>,
+    /// Whether the stream is broken, so the tunnel should be opened again.
+    broken: Arc<AtomicBool>,
 }
 
 impl LeaseKeepAliveTunnel {
-    /// Creates a new `LeaseClient`.
-    fn new(client: &LeaseClient) -> Self {
+    /// Opens a new `LeaseKeepAliveTunnel` with the current auth token,
+    /// the responses are sent to `resp_sender`.
+    fn new(
+        client: &LeaseClient,
+        token: &Arc<AuthToken>,
+        resp_sender: Sender<Result<EtcdLeaseKeepAliveResponse>>,
+    ) -> Result<Self> {
         let (req_sender, req_receiver) = unbounded::<EtcdLeaseKeepAliveRequest>();
-        let (resp_sender, resp_receiver) = unbounded::<Result<EtcdLeaseKeepAliveResponse>>();
 
         let (shutdown_tx, shutdown_rx) = unbounded();
         let shutdown_reponse = shutdown_rx.clone();
+        let broken = Arc::new(AtomicBool::new(false));
         // Monitor inbound lease response and transfer to the receiver
-        let (mut client_req_sender, mut client_resp_receiver) = client
-            .lease_keep_alive()
-            .unwrap_or_else(|e| panic!("Fail to lease_keep_alive, the error is: {}", e));
+        let (mut client_req_sender, mut client_resp_receiver) =
+            client.lease_keep_alive_opt(token.call_option(&CallOptions::default())?)?;
+        let send_broken = Arc::clone(&broken);
         smol::spawn(async move {
             let mut shutdown_rx = shutdown_rx.into_future().fuse();
             #[allow(clippy::mut_mut)]
             while let Ok(req) = req_receiver.recv().await {
                 let lease_keep_alive_request: rpc::LeaseKeepAliveRequest = req.into();
 
-                futures::select! {
+                let res = futures::select! {
                     res = client_req_sender.send(
                         (lease_keep_alive_request, WriteFlags::default())
-                    ).fuse() => res.unwrap_or_else(
-                        |e| panic!("Fail to send request, the error is {}", e)
-                    ),
+                    ).fuse() => res,
                     _ = shutdown_rx => return
                 };
+                if let Err(e) = res {
+                    warn!("Fail to send keep alive request, the error is: {}", e);
+                    send_broken.store(true, Ordering::Release);
+                    return;
+                }
             }
         })
         .detach();
 
+        let recv_broken = Arc::clone(&broken);
+        let token = Arc::clone(token);
         smol::spawn(async move {
             let mut shutdown_rx = shutdown_reponse.into_future().fuse();
             loop {
                 #[allow(clippy::mut_mut)]
-                let resp = futures::select! {
-                    resp_opt = client_resp_receiver.next().fuse() => resp_opt.unwrap_or_else(
-                        || panic!("Fail to receive client response")
-                    ),
+                let resp_opt = futures::select! {
+                    resp_opt = client_resp_receiver.next().fuse() => resp_opt,
                     _ = shutdown_rx => return
                 };
-                match resp {
-                    Ok(resp) => {
-                        resp_sender
-                            .send(Ok(From::from(resp)))
-                            .await
-                            .unwrap_or_else(|e| {
-                                panic!("failed to send response, the error is: {}", e)
-                            });
+                let resp = match resp_opt {
+                    Some(Ok(resp)) => Ok(From::from(resp)),
+                    Some(Err(e)) => {
+                        // The tunnel is opened again with the new token
+                        let _refreshed = token.refresh_on(&e).await;
+                        recv_broken.store(true, Ordering::Release);
+                        Err(From::from(e))
                     }
-                    Err(e) => {
-                        resp_sender
-                            .send(Err(From::from(e)))
-                            .await
-                            .unwrap_or_else(|e| {
-                                panic!("failed to send response, the error is: {}", e)
-                            });
+                    None => {
+                        recv_broken.store(true, Ordering::Release);
+                        return;
                     }
                 };
+                let is_err = resp.is_err();
+                if resp_sender.send(resp).await.is_err() || is_err {
+                    return;
+                }
             }
         })
         .detach();
 
-        Self {
+        Ok(Self {
             req_sender,
-            resp_receiver: Some(resp_receiver),
             shutdown: Some(shutdown_tx),
-        }
+            broken,
+        })
     }
 
-    /// Takes `resp_receiver`
-    fn take_resp_receiver(&mut self) -> Receiver<Result<EtcdLeaseKeepAliveResponse>> {
-        self.resp_receiver
-            .take()
-            .unwrap_or_else(|| panic!("failed fetch LeaseKeepAliveResponse"))
+    /// Returns `true` if the stream is broken.
+    fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::Acquire)
     }
 }
 
@@ -194,22 +195,34 @@ impl Shutdown for LeaseKeepAliveTunnel {
 pub struct Lease {
     /// Etcd lease client provides lease related operations.
     client: LeaseClient,
-    /// A tunnel used to communicate with Etcd server to keep lease alive.
-    keep_alive_tunnel: Arc<Lazy<LeaseKeepAliveTunnel>>,
+    /// A tunnel used to communicate with Etcd server to keep lease alive, it is opened
+    /// on the first keep alive request, and opened again if its stream is broken.
+    keep_alive_tunnel: Arc<Mutex<Option<LeaseKeepAliveTunnel>>>,
+    /// A channel sender to send keep alive response, shared by the tunnels.
+    resp_sender: Sender<Result<EtcdLeaseKeepAliveResponse>>,
+    /// A channel receiver to receive keep alive response.
+    resp_receiver: Receiver<Result<EtcdLeaseKeepAliveResponse>>,
+    /// Auth token attached to RPCs.
+    token: Arc<AuthToken>,
+    /// Retry policy of RPCs.
+    retry_policy: Arc<RetryPolicy>,
 }
 
 impl Lease {
     /// Creates a new `LeaseClient`.
-    pub(crate) fn new(client: LeaseClient) -> Self {
-        let keep_alive_tunnel = {
-            let client = client.clone();
-            Arc::new(Lazy::new(move || {
-                LeaseKeepAliveTunnel::new(&client.clone())
-            }))
-        };
+    pub(crate) fn new(
+        client: LeaseClient,
+        token: Arc<AuthToken>,
+        retry_policy: Arc<RetryPolicy>,
+    ) -> Self {
+        let (resp_sender, resp_receiver) = unbounded::<Result<EtcdLeaseKeepAliveResponse>>();
         Self {
             client,
-            keep_alive_tunnel,
+            keep_alive_tunnel: Arc::new(Mutex::new(None)),
+            resp_sender,
+            resp_receiver,
+            token,
+            retry_policy,
         }
     }
 
@@ -219,10 +232,17 @@ impl Lease {
     /// Will return `Err` if tunnel is shut down.
     #[inline]
     pub async fn grant(&mut self, req: EtcdLeaseGrantRequest) -> Result<EtcdLeaseGrantResponse> {
-        let resp = retryable!(|| async {
-            let resp = self.client.lease_grant_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
+        let options = req.call_options().start();
+        let resp = self
+            .retry_policy
+            .retry(RetryOperation::LeaseGrant, options.deadline, || async {
+                let resp = self.client.lease_grant_async_opt(
+                    &req.clone().into(),
+                    self.token.call_option(&options)?,
+                )?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
         Ok(resp)
     }
 
@@ -232,33 +252,47 @@ impl Lease {
     /// Will return `Err` if tunnel is shut down.
     #[inline]
     pub async fn revoke(&mut self, req: EtcdLeaseRevokeRequest) -> Result<EtcdLeaseRevokeResponse> {
-        let resp = retryable!(|| async {
-            let resp = self.client.lease_revoke_async(&req.clone().into())?;
-            Ok(From::from(resp.await?))
-        });
+        let options = req.call_options().start();
+        let resp = self
+            .retry_policy
+            .retry(RetryOperation::LeaseRevoke, options.deadline, || async {
+                let resp = self.client.lease_revoke_async_opt(
+                    &req.clone().into(),
+                    self.token.call_option(&options)?,
+                )?;
+                Ok(From::from(self.token.check(resp.await).await?))
+            })
+            .await?;
         Ok(resp)
     }
 
     /// Fetches keep alive response stream.
+    ///
+    /// The stream is kept when the tunnel is opened again, and an error of the broken
+    /// tunnel is sent to the stream.
     #[inline]
     pub async fn keep_alive_responses(
         &mut self,
     ) -> impl Stream<Item = Result<EtcdLeaseKeepAliveResponse>> {
-        self.keep_alive_tunnel.write().await.take_resp_receiver()
+        self.resp_receiver.clone()
     }
 
     /// Performs a lease refreshing operation.
     /// # Errors
     ///
-    /// Will return `Err` if tunnel is shut down.
+    /// Will return `Err` if the tunnel fails to open or is shut down.
     #[inline]
     pub async fn keep_alive(&mut self, req: EtcdLeaseKeepAliveRequest) -> Result<()> {
-        self.keep_alive_tunnel
-            .write()
-            .await
-            .req_sender
-            .send(req)
-            .await?;
+        let mut guard = self.keep_alive_tunnel.lock().await;
+        // A broken tunnel is dropped, which stops its tasks, and a new one is opened with
+        // the current auth token
+        let tunnel = match guard.take().filter(|tunnel| !tunnel.is_broken()) {
+            Some(tunnel) => tunnel,
+            None => LeaseKeepAliveTunnel::new(&self.client, &self.token, self.resp_sender.clone())?,
+        };
+        let res = tunnel.req_sender.send(req).await;
+        *guard = Some(tunnel);
+        res?;
         Ok(())
     }
 
@@ -271,6 +305,9 @@ impl Lease {
     pub async fn shutdown(&mut self) -> Result<()> {
         // If we implemented `Shutdown` for this, callers would need it in scope in
         // order to call this method.
-        self.keep_alive_tunnel.evict().await
+        match self.keep_alive_tunnel.lock().await.take() {
+            Some(mut tunnel) => tunnel.shutdown().await,
+            None => Ok(()),
+        }
     }
 }
@@ -1,4 +1,5 @@
 use crate::protos::rpc::{LeaseRevokeRequest, LeaseRevokeResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
 
@@ -7,6 +8,8 @@ use clippy_utilities::Cast;
 pub struct EtcdLeaseRevokeRequest {
     /// Etcd lease revoke request.
     proto: LeaseRevokeRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdLeaseRevokeRequest {
@@ -19,7 +22,22 @@ impl EtcdLeaseRevokeRequest {
             ..LeaseRevokeRequest::default()
         };
 
-        Self { proto }
+        Self {
+            proto,
+            options: CallOptions::default(),
+        }
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
     }
 }
 
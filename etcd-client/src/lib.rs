@@ -87,28 +87,43 @@
 )]
 
 pub use auth::{Auth, EtcdAuthenticateRequest, EtcdAuthenticateResponse};
-pub use client::{Client, ClientConfig};
+pub use call_options::CallOptions;
+pub use client::{Client, ClientConfig, TlsConfig};
 pub use clippy_utilities::OverflowArithmetic;
 pub use error::EtcdError;
 pub use kv::{
-    EtcdDeleteRequest, EtcdDeleteResponse, EtcdGetRequest, EtcdGetResponse, EtcdKeyValue,
-    EtcdPutRequest, EtcdPutResponse, EtcdRangeRequest, EtcdRangeResponse, EtcdTxnRequest,
-    EtcdTxnResponse, KeyRange, Kv, TxnCmp, TxnOpResponse,
+    BulkChunk, BulkOptions, BulkResponse, CacheCapacity, CompactionRetention, EtcdCompactRequest,
+    EtcdCompactResponse, EtcdDeleteRequest, EtcdDeleteResponse, EtcdGetManyResponse,
+    EtcdGetRequest, EtcdGetResponse, EtcdKeyValue, EtcdPutRequest, EtcdPutResponse,
+    EtcdRangeRequest, EtcdRangeResponse, EtcdTxnRequest, EtcdTxnResponse, EvictionPolicy,
+    EvictionPolicyKind, ImportMode, ImportOptions, KeyChange, KeyRange, Kv, LfuPolicy, LruPolicy,
+    MirrorItem, MirrorStream, RangeStream, SortOrder, SortTarget, TxnCmp, TxnOpResponse,
+    UpdateAction, WTinyLfuPolicy,
 };
+/// The client cache, which is exported for the benchmarks and is not a stable API.
+#[doc(hidden)]
+pub use kv::{Cache, CacheHit};
 pub use lease::{
     EtcdLeaseGrantRequest, EtcdLeaseGrantResponse, EtcdLeaseKeepAliveRequest,
     EtcdLeaseKeepAliveResponse, EtcdLeaseRevokeRequest, EtcdLeaseRevokeResponse, Lease,
 };
 pub use lock::Lock;
 pub use lock::{EtcdLockRequest, EtcdLockResponse, EtcdUnlockRequest, EtcdUnlockResponse};
+pub use namespace::{
+    Namespace, NamespaceKv, NamespaceLock, NamespaceWatch, NamespaceWatchReceiver,
+};
 pub use response_header::ResponseHeader;
-pub use watch::{EtcdWatchRequest, EtcdWatchResponse, Event, EventType, Watch};
+pub use retry::{RetryOperation, RetryPolicy};
+pub use stm::{Isolation, StmFuture, StmTxn};
+pub use watch::{EtcdWatchRequest, EtcdWatchResponse, Event, EventType, KeyVersion, Watch};
 
 use backoff::{future::Sleeper, Notify};
 use std::{future::Future, pin::Pin, time::Duration};
 
 /// Auth mod for authentication operations.
 mod auth;
+/// Call options mod for per-request deadlines and metadata.
+mod call_options;
 /// Client mod for Etcd client operations.
 mod client;
 /// Error mod for Etcd client error.
@@ -121,63 +136,22 @@ mod lazy;
 mod lease;
 /// Lock mod for lock operations.
 mod lock;
+/// Namespace mod for key-prefixed views of the client.
+mod namespace;
 /// Etcd client request and response protos
 mod proto;
 /// Etcd API response header
 mod response_header;
+/// Retry mod for retrying failed RPCs.
+mod retry;
+/// Software transactional memory mod over transactions.
+mod stm;
 /// Watch mod for watch operations.
 mod watch;
 
 /// Result with error information
 pub type Result<T> = std::result::Result<T, EtcdError>;
 
-/// The default value for current interval value
-pub const CURRENT_INTERVAL_VALUE: u64 = 1;
-/// The key of the default value for current interval environment variable
-pub const CURRENT_INTERVAL_ENV_KEY: &str = "CURRENT_INTERVAL";
-/// The default value for current initial value
-pub const INITIAL_INTERVAL_VALUE: u64 = 1;
-/// The key of the default value for initial interval environment variable
-pub const INITIAL_INTERVAL_ENV_KEY: &str = "INITIAL_INTERVAL";
-/// The default value for max elapsed value
-pub const MAX_ELAPSED_TIME_VALUE: u64 = 10;
-/// The key of the default value for max elapsed environment variable
-pub const MAX_ELAPSED_TIME_ENV_KEY: &str = "MAX_ELAPSED_TIME";
-
-/// A retry macro to immediately attempt a function call after failure
-#[macro_export]
-macro_rules! retryable {
-    ($args:expr) => {
-        backoff::future::Retry::new(
-            crate::SmolSleeper,
-            ExponentialBackoff {
-                current_interval: Duration::from_secs(
-                    match std::env::var(CURRENT_INTERVAL_ENV_KEY) {
-                        Ok(val) => val.parse().unwrap(),
-                        Err(_) => CURRENT_INTERVAL_VALUE,
-                    },
-                ),
-                initial_interval: Duration::from_secs(
-                    match std::env::var(INITIAL_INTERVAL_ENV_KEY) {
-                        Ok(val) => val.parse().unwrap(),
-                        Err(_) => INITIAL_INTERVAL_VALUE,
-                    },
-                ),
-                max_elapsed_time: Some(Duration::from_secs(
-                    match std::env::var(MAX_ELAPSED_TIME_ENV_KEY) {
-                        Ok(val) => val.parse().unwrap(),
-                        Err(_) => MAX_ELAPSED_TIME_VALUE,
-                    },
-                )),
-                ..ExponentialBackoff::default()
-            },
-            crate::NoopNotify,
-            $args,
-        )
-        .await?
-    };
-}
-
 /// The notifier does nothing
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy)]
@@ -249,6 +223,25 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_invalid_cache_config() {
+        let cfg = ClientConfig::new(
+            vec![DEFAULT_ETCD_ENDPOINT1_FOR_TEST.to_owned()],
+            None,
+            64,
+            true,
+        )
+        .with_cache_capacity(CacheCapacity::Bytes(1024))
+        .with_eviction_policy(EvictionPolicyKind::WTinyLfu);
+        assert!(
+            matches!(
+                smol::block_on(Client::connect(cfg)),
+                Err(EtcdError::InvalidConfig(_))
+            ),
+            "WTinyLfu should be rejected with a capacity in bytes"
+        );
+    }
+
     async fn test_watch(key_prefix: &str) -> Result<()> {
         /// For one task to watch put and deletion of a key to check is it support multi watchers
         async fn watch_one(watch_key: &str, client: Client) {
@@ -445,11 +438,137 @@ mod tests {
         log::debug!("test_transaction");
         let client = build_etcd_client().await?;
         test_compose(&client).await?;
+        test_stm(&client).await?;
+        test_update(&client).await?;
         clean_etcd(&client).await?;
         client.shutdown().await?;
         Ok(())
     }
 
+    async fn test_stm(client: &Client) -> Result<()> {
+        async fn increase(client: Client, times: usize) -> Result<()> {
+            for _ in 0..times {
+                client
+                    .kv()
+                    .stm(Isolation::Serializable, |txn| {
+                        Box::pin(async move {
+                            let count = txn
+                                .get("stm_count")
+                                .await?
+                                .map_or(0, |v| String::from_utf8_lossy(&v).parse().unwrap_or(0));
+                            txn.put("stm_count", format!("{}", count.overflow_add(1_i32)));
+                            Ok(())
+                        })
+                    })
+                    .await?;
+            }
+            Ok(())
+        }
+
+        let tasks = (0..4)
+            .map(|_| smol::spawn(increase(client.clone(), 5)))
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await?;
+        }
+        let mut resp = client.kv().get(EtcdGetRequest::new("stm_count")).await?;
+        assert_eq!(
+            resp.take_kvs()
+                .get(0)
+                .unwrap_or_else(|| panic!("The counter should exist"))
+                .value_str(),
+            "20",
+            "Concurrent increments should not be lost"
+        );
+
+        // A commit with more writes than max txn ops fails without retrying
+        let mut attempts = 0_usize;
+        let res = client
+            .kv()
+            .stm(Isolation::Serializable, |txn| {
+                attempts = attempts.overflow_add(1);
+                Box::pin(async move {
+                    for i in 0..=128 {
+                        txn.put(format!("stm_key{}", i), "");
+                    }
+                    Ok(())
+                })
+            })
+            .await;
+        assert!(
+            matches!(res, Err(EtcdError::TxnOpsExceeded(129, 128))),
+            "The commit exceeding max txn ops should be rejected"
+        );
+        assert_eq!(attempts, 1, "The rejected commit should not be retried");
+        Ok(())
+    }
+
+    async fn test_update(client: &Client) -> Result<()> {
+        let kv = client.kv();
+        let increase = |kv: Option<EtcdKeyValue>| {
+            let count = kv.map_or(0_i32, |kv| kv.value_str().parse().unwrap_or(0));
+            UpdateAction::Put(format!("{}", count.overflow_add(1)).into_bytes())
+        };
+        let tasks = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                smol::spawn(async move {
+                    for _ in 0..5 {
+                        let _revision = client.kv().update("update_count", increase).await?;
+                    }
+                    Ok::<(), EtcdError>(())
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await?;
+        }
+        let mut resp = kv.get(EtcdGetRequest::new("update_count")).await?;
+        assert_eq!(
+            resp.take_kvs()
+                .get(0)
+                .unwrap_or_else(|| panic!("The counter should exist"))
+                .value_str(),
+            "20",
+            "Concurrent updates should not be lost"
+        );
+
+        let revision = kv.update("update_count", |_| UpdateAction::Noop).await?;
+        assert_eq!(
+            kv.update("update_count", |_| UpdateAction::Noop).await?,
+            revision,
+            "No-op update should not write"
+        );
+        let _revision = kv.update("update_count", |_| UpdateAction::Delete).await?;
+        let mut resp = kv.get(EtcdGetRequest::new("update_count")).await?;
+        assert!(resp.take_kvs().is_empty(), "The counter should be deleted");
+
+        // The update keeps the lease of the key
+        let lease_id = client
+            .lease()
+            .grant(EtcdLeaseGrantRequest::new(Duration::from_secs(60)))
+            .await?
+            .id();
+        let mut put = EtcdPutRequest::new("update_lease", "0");
+        put.set_lease(lease_id);
+        kv.put(put).await?;
+        let _revision = kv.update("update_lease", increase).await?;
+        let mut resp = kv.get(EtcdGetRequest::new("update_lease")).await?;
+        let updated = resp
+            .take_kvs()
+            .pop()
+            .unwrap_or_else(|| panic!("The key should exist"));
+        assert_eq!(updated.value_str(), "1");
+        assert_eq!(
+            updated.lease(),
+            lease_id,
+            "The update should keep the lease"
+        );
+        kv.delete(EtcdDeleteRequest::new(KeyRange::key("update_lease")))
+            .await?;
+        Ok(())
+    }
+
     async fn test_compose(client: &Client) -> Result<()> {
         let revision;
         {
@@ -495,6 +614,41 @@ mod tests {
             }
         }
 
+        // Create the key only if it is absent
+        let create_txn = |value: &str| {
+            EtcdTxnRequest::new()
+                .when_not_exists(KeyRange::key("txn_create"))
+                .and_then(EtcdPutRequest::new("txn_create", value))
+                .or_else(EtcdRangeRequest::new(KeyRange::key("txn_create")))
+        };
+        let txn_resp = client.kv().txn(create_txn("v1")).await?;
+        assert!(txn_resp.is_success(), "The absent key should be created");
+        let mut txn_resp = client.kv().txn(create_txn("v2")).await?;
+        assert!(
+            !txn_resp.is_success(),
+            "The existing key should not be overwritten"
+        );
+        let mut range_resp = txn_resp
+            .take_responses()
+            .pop()
+            .and_then(TxnOpResponse::into_range)
+            .unwrap_or_else(|| panic!("There should be a range response"));
+        let kv = range_resp
+            .take_kvs()
+            .pop()
+            .unwrap_or_else(|| panic!("Fail to get key value from RangeResponse"));
+        assert_eq!(kv.value_str(), "v1", "The value should not be overwritten");
+        let txn_resp = client
+            .kv()
+            .txn(
+                EtcdTxnRequest::new()
+                    .when_exists(KeyRange::key("txn_create"))
+                    .when_lease(KeyRange::key("txn_create"), TxnCmp::Equal, kv.lease())
+                    .and_then(EtcdDeleteRequest::new(KeyRange::key("txn_create"))),
+            )
+            .await?;
+        assert!(txn_resp.is_success(), "The existing key has no lease");
+
         // The failure operation should not be proccessed.
         let req = EtcdRangeRequest::new(KeyRange::key("bar"));
         let range_resp = client.kv().range(req).await?;
@@ -512,6 +666,16 @@ mod tests {
         let client = build_etcd_client().await?;
         test_list_prefix(&client).await?;
         test_range_query(&client).await?;
+        test_range_stream(&client).await?;
+        test_compact(&client).await?;
+        test_bulk(&client).await?;
+        test_get_many(&client).await?;
+        test_history(&client).await?;
+        test_diff(&client).await?;
+        test_dump(&client).await?;
+        test_mirror(&client).await?;
+        test_range_cache(&client).await?;
+        test_absent_cache(&client).await?;
         clean_etcd(&client).await?;
         client.shutdown().await?;
         Ok(())
@@ -582,6 +746,563 @@ mod tests {
         Ok(())
     }
 
+    async fn test_range_stream(client: &Client) -> Result<()> {
+        use futures::TryStreamExt;
+
+        let prefix = "43_";
+        for v in 0_i32..10_i32 {
+            client
+                .kv()
+                .put(EtcdPutRequest::new(
+                    format!("{}{}", prefix, v),
+                    format!("{}", v),
+                ))
+                .await?;
+        }
+
+        let mut stream = client.kv().range_stream(KeyRange::prefix(prefix), 3);
+        assert_eq!(stream.revision(), None, "No page should be fetched yet");
+        let first = stream
+            .try_next()
+            .await?
+            .unwrap_or_else(|| panic!("There should be key-value pairs"));
+        let revision = stream
+            .revision()
+            .unwrap_or_else(|| panic!("The revision should be set by the first page"));
+
+        // Keys put after the first page should not be seen
+        client
+            .kv()
+            .put(EtcdPutRequest::new(format!("{}99", prefix), "99"))
+            .await?;
+        let mut keys = vec![first.key_str().to_owned()];
+        keys.extend(
+            stream
+                .map_ok(|kv| kv.key_str().to_owned())
+                .try_collect::<Vec<_>>()
+                .await?,
+        );
+        let expected = (0_i32..10_i32)
+            .map(|v| format!("{}{}", prefix, v))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, expected, "The keys of the stream are wrong");
+        assert!(first.mod_revision() <= revision, "Wrong snapshot revision");
+
+        client
+            .kv()
+            .delete(EtcdDeleteRequest::new(KeyRange::prefix(prefix)))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_bulk(client: &Client) -> Result<()> {
+        let prefix = "44_";
+        let options = BulkOptions::new().with_max_txn_ops(16);
+        let puts = (0_i32..100_i32)
+            .map(|v| EtcdPutRequest::new(format!("{}{:03}", prefix, v), format!("{}", v)));
+        let resp = client.kv().put_many(puts, options.clone()).await?;
+        assert!(resp.is_success(), "All the chunks should be applied");
+        assert_eq!(
+            resp.chunks().len(),
+            7,
+            "The puts should be split by max txn ops"
+        );
+        let mut range_resp = client
+            .kv()
+            .range(EtcdRangeRequest::new(KeyRange::prefix(prefix)))
+            .await?;
+        assert_eq!(
+            range_resp.take_kvs().len(),
+            100,
+            "All the keys should be put"
+        );
+
+        let puts = (0_i32..100_i32).map(|v| EtcdPutRequest::new(format!("{}{:03}", prefix, v), ""));
+        assert!(
+            matches!(
+                client
+                    .kv()
+                    .put_many(puts, options.clone().with_atomic(true))
+                    .await,
+                Err(EtcdError::TooManyTxnOps)
+            ),
+            "Atomic mode should refuse to chunk"
+        );
+
+        // The puts of the same key are applied in different txns
+        let puts = ["1", "2"].map(|v| EtcdPutRequest::new(format!("{}dup", prefix), v));
+        let resp = client
+            .kv()
+            .put_many(puts.clone(), options.clone().with_concurrency(1))
+            .await?;
+        assert!(resp.is_success(), "The duplicate puts should be applied");
+        assert_eq!(
+            resp.chunks().len(),
+            2,
+            "The duplicate key should start a new txn"
+        );
+        assert!(
+            matches!(
+                client
+                    .kv()
+                    .put_many(puts, options.clone().with_atomic(true))
+                    .await,
+                Err(EtcdError::OverlappingTxnOps(_))
+            ),
+            "Atomic mode should refuse the duplicate puts"
+        );
+
+        // The txns putting the same key are applied in order, the last put wins
+        let puts = (0_i32..64_i32)
+            .map(|v| EtcdPutRequest::new(format!("{}dup", prefix), format!("{}", v)));
+        let resp = client
+            .kv()
+            .put_many(
+                puts,
+                options.clone().with_max_txn_ops(1).with_concurrency(8),
+            )
+            .await?;
+        assert!(resp.is_success(), "The duplicate puts should be applied");
+        let mut get_resp = client
+            .kv()
+            .get(EtcdGetRequest::new(format!("{}dup", prefix)))
+            .await?;
+        assert_eq!(
+            get_resp
+                .take_kvs()
+                .pop()
+                .map(|kv| kv.value_str().to_owned()),
+            Some("63".to_owned()),
+            "The last put of the key should win"
+        );
+        client
+            .kv()
+            .delete(EtcdDeleteRequest::new(KeyRange::key(format!(
+                "{}dup",
+                prefix
+            ))))
+            .await?;
+
+        let deletes = (0_i32..100_i32)
+            .map(|v| EtcdDeleteRequest::new(KeyRange::key(format!("{}{:03}", prefix, v))));
+        let resp = client.kv().delete_many(deletes, options).await?;
+        assert!(resp.is_success(), "All the chunks should be applied");
+        let mut range_resp = client
+            .kv()
+            .range(EtcdRangeRequest::new(KeyRange::prefix(prefix)))
+            .await?;
+        assert!(
+            range_resp.take_kvs().is_empty(),
+            "All the keys should be deleted"
+        );
+        Ok(())
+    }
+
+    async fn test_get_many(client: &Client) -> Result<()> {
+        let revision = client
+            .kv()
+            .put(EtcdPutRequest::new("45_foo", "bar"))
+            .await?
+            .take_header()
+            .unwrap_or_else(|| panic!("Fail to take header from response"))
+            .revision();
+        client
+            .kv()
+            .put(EtcdPutRequest::new("45_baz", "qux"))
+            .await?;
+        // Cache one of the keys
+        client.kv().get(EtcdGetRequest::new("45_foo")).await?;
+
+        let resp = client
+            .kv()
+            .get_many(vec!["45_foo", "45_baz", "45_missing"])
+            .await?;
+        assert!(
+            resp.revision() > revision.cast(),
+            "The keys should be read at the latest revision"
+        );
+        let value = |key: &str| resp.get(key.as_bytes()).map(|kv| kv.value_str().to_owned());
+        assert_eq!(
+            value("45_foo"),
+            Some("bar".to_owned()),
+            "Wrong value of 45_foo"
+        );
+        assert_eq!(
+            value("45_baz"),
+            Some("qux".to_owned()),
+            "Wrong value of 45_baz"
+        );
+        assert_eq!(value("45_missing"), None, "45_missing should not exist");
+        assert!(
+            matches!(
+                client
+                    .kv()
+                    .get_many((0..=128).map(|i| format!("45_{}", i)))
+                    .await,
+                Err(EtcdError::TxnOpsExceeded(129, 128))
+            ),
+            "The keys more than max txn ops should be rejected"
+        );
+
+        client
+            .kv()
+            .delete(EtcdDeleteRequest::new(KeyRange::prefix("45_")))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_history(client: &Client) -> Result<()> {
+        let kv = client.kv();
+        let from = kv
+            .put(EtcdPutRequest::new("history_foo", "v1"))
+            .await?
+            .take_header()
+            .unwrap_or_else(|| panic!("Fail to take header from response"))
+            .revision();
+        kv.put(EtcdPutRequest::new("history_bar", "v1")).await?;
+        kv.put(EtcdPutRequest::new("history_foo", "v2")).await?;
+        kv.delete(EtcdDeleteRequest::new(KeyRange::key("history_foo")))
+            .await?;
+        kv.put(EtcdPutRequest::new("history_foo", "v3")).await?;
+
+        let history = kv.history("history_foo", from.cast(), usize::MAX).await?;
+        let values = history
+            .iter()
+            .map(|version| version.kv().map(|kv| kv.value_str().to_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                Some("v1".to_owned()),
+                Some("v2".to_owned()),
+                None,
+                Some("v3".to_owned())
+            ],
+            "The history of the key is wrong"
+        );
+        assert!(
+            history
+                .windows(2)
+                .all(|pair| pair.get(0).map(KeyVersion::revision)
+                    < pair.get(1).map(KeyVersion::revision)),
+            "The history should be ordered by revision"
+        );
+
+        // The replay ends even if the key is not modified since the last version
+        let bar_revision = kv
+            .put(EtcdPutRequest::new("history_bar", "v2"))
+            .await?
+            .take_header()
+            .unwrap_or_else(|| panic!("Fail to take header from response"))
+            .revision();
+        let options = CallOptions::new().with_timeout(Duration::from_secs(5));
+        let history = kv
+            .history_with_options("history_foo", from.cast(), usize::MAX, options.clone())
+            .await?;
+        assert_eq!(
+            history.len(),
+            4,
+            "The history should end at the current revision"
+        );
+        let bar_history = kv
+            .history_with_options(
+                "history_bar",
+                bar_revision.overflow_add(1).cast(),
+                usize::MAX,
+                options,
+            )
+            .await?;
+        assert!(
+            bar_history.is_empty(),
+            "The unmodified key should have no history"
+        );
+
+        let first_two = kv
+            .history(
+                "history_foo",
+                from.cast(),
+                history.get(1).map_or(0, KeyVersion::revision),
+            )
+            .await?;
+        assert_eq!(
+            first_two.len(),
+            2,
+            "The history should end at the to revision"
+        );
+
+        // The history before the compaction is unavailable
+        kv.compact(EtcdCompactRequest::new(from.cast())).await?;
+        let err = kv
+            .history(
+                "history_foo",
+                from.cast::<usize>().overflow_sub(1),
+                usize::MAX,
+            )
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("Listing compacted history should fail"));
+        assert!(
+            err.is_compacted(),
+            "The error should be compacted, but is {}",
+            err
+        );
+
+        kv.delete(EtcdDeleteRequest::new(KeyRange::key("history_foo")))
+            .await?;
+        kv.delete(EtcdDeleteRequest::new(KeyRange::key("history_bar")))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_diff(client: &Client) -> Result<()> {
+        let kv = client.kv();
+        for key in ["46_a", "46_b", "46_c"] {
+            kv.put(EtcdPutRequest::new(key, "old")).await?;
+        }
+        let old_revision = kv.current_revision().await?;
+        kv.put(EtcdPutRequest::new("46_b", "new")).await?;
+        kv.delete(EtcdDeleteRequest::new(KeyRange::key("46_c")))
+            .await?;
+        kv.put(EtcdPutRequest::new("46_d", "new")).await?;
+
+        let changes = kv.diff(KeyRange::prefix("46_"), old_revision, 0).await?;
+        let changes = changes
+            .iter()
+            .map(|change| match *change {
+                KeyChange::Added(ref new) => format!("+{}={}", new.key_str(), new.value_str()),
+                KeyChange::Modified { ref old, ref new } => format!(
+                    "~{}={}->{}",
+                    new.key_str(),
+                    old.value_str(),
+                    new.value_str()
+                ),
+                KeyChange::Deleted(ref old) => format!("-{}={}", old.key_str(), old.value_str()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec!["~46_b=old->new", "-46_c=old", "+46_d=new"],
+            "The diff of the snapshots is wrong"
+        );
+
+        kv.delete(EtcdDeleteRequest::new(KeyRange::prefix("46_")))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_dump(client: &Client) -> Result<()> {
+        let kv = client.kv();
+        for v in 0_i32..5_i32 {
+            kv.put(EtcdPutRequest::new(
+                format!("47_src/{}", v),
+                format!("{}", v),
+            ))
+            .await?;
+        }
+        let mut dump = Vec::new();
+        let revision = kv.export(KeyRange::prefix("47_src/"), &mut dump).await?;
+        assert!(revision > 0, "The dump should have a revision");
+        let lines = String::from_utf8_lossy(&dump).lines().count();
+        assert_eq!(
+            lines, 7,
+            "The dump should have a header, 5 entries and a trailer"
+        );
+
+        kv.put(EtcdPutRequest::new("47_dst/0", "existing")).await?;
+        let options = ImportOptions::new()
+            .with_mode(ImportMode::SkipExisting)
+            .with_rewrite_prefix("47_src/", "47_dst/");
+        let resp = kv.import(dump.as_slice(), options).await?;
+        assert!(resp.is_success(), "All the chunks should be applied");
+        let mut range_resp = kv
+            .range(EtcdRangeRequest::new(KeyRange::prefix("47_dst/")))
+            .await?;
+        let values = range_resp
+            .take_kvs()
+            .iter()
+            .map(|kv| kv.value_str().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec!["existing", "1", "2", "3", "4"],
+            "The existing key should be skipped"
+        );
+
+        // A dump with a corrupted entry should be rejected
+        let header_len = dump
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(0, |i| i.overflow_add(1));
+        let mut corrupted = dump.clone();
+        if let Some(byte) = corrupted.iter_mut().skip(header_len).find(|b| **b == b'1') {
+            *byte = b'2';
+        }
+        let err = kv
+            .import(corrupted.as_slice(), ImportOptions::new())
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("Importing a corrupted dump should fail"));
+        assert!(
+            matches!(err, EtcdError::InvalidDump(_)),
+            "The error should be invalid dump, but is {}",
+            err
+        );
+
+        kv.delete(EtcdDeleteRequest::new(KeyRange::prefix("47_")))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_mirror(client: &Client) -> Result<()> {
+        use futures::TryStreamExt;
+
+        let kv = client.kv();
+        for v in 0_i32..3_i32 {
+            kv.put(EtcdPutRequest::new(format!("48_{}", v), format!("{}", v)))
+                .await?;
+        }
+        let mut mirror = kv.mirror(KeyRange::prefix("48_"));
+        let revision = match mirror.try_next().await? {
+            Some(MirrorItem::Reset(revision)) => revision,
+            _ => panic!("The mirror should start with a reset"),
+        };
+        match mirror.try_next().await? {
+            Some(MirrorItem::Chunk(kvs)) => assert_eq!(kvs.len(), 3, "Wrong snapshot chunk"),
+            _ => panic!("The snapshot should follow the reset"),
+        }
+
+        kv.put(EtcdPutRequest::new("48_3", "3")).await?;
+        match mirror.try_next().await? {
+            Some(MirrorItem::Update(update_revision, mut events)) => {
+                assert!(update_revision > revision, "Wrong update revision");
+                let key = events
+                    .get_mut(0)
+                    .and_then(Event::take_kvs)
+                    .map(|kv| kv.key_str().to_owned());
+                assert_eq!(key, Some("48_3".to_owned()), "Wrong update event");
+            }
+            _ => panic!("The update should follow the snapshot"),
+        }
+
+        kv.delete(EtcdDeleteRequest::new(KeyRange::prefix("48_")))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_compact(client: &Client) -> Result<()> {
+        let revision = client
+            .kv()
+            .put(EtcdPutRequest::new("compact_foo", "bar"))
+            .await?
+            .take_header()
+            .unwrap_or_else(|| panic!("Fail to take header from response"))
+            .revision();
+        client
+            .kv()
+            .compact(EtcdCompactRequest::new(revision.cast()))
+            .await?;
+
+        let err = client
+            .kv()
+            .compact(EtcdCompactRequest::new(revision.cast()))
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("Compacting a compacted revision should fail"));
+        assert!(
+            err.is_compacted(),
+            "The error should be compacted, but is {}",
+            err
+        );
+
+        let mut req = EtcdRangeRequest::new(KeyRange::key("compact_foo"));
+        req.set_revision(revision.cast::<usize>().overflow_sub(1));
+        let err = client
+            .kv()
+            .range(req)
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("Reading a compacted revision should fail"));
+        assert!(
+            err.is_compacted(),
+            "The error should be compacted, but is {}",
+            err
+        );
+        assert_eq!(
+            err.compact_revision(),
+            None,
+            "A range doesn't tell the compacted revision"
+        );
+        Ok(())
+    }
+
+    async fn test_range_cache(client: &Client) -> Result<()> {
+        let kv = client.kv();
+        kv.put(EtcdPutRequest::new("50_b", "2")).await?;
+        kv.put(EtcdPutRequest::new("50_a", "3")).await?;
+        kv.cache_range(KeyRange::prefix("50_")).await?;
+
+        // Writes through the client are visible once they return
+        kv.put(EtcdPutRequest::new("50_c", "1")).await?;
+        let revision = kv
+            .delete(EtcdDeleteRequest::new(KeyRange::key("50_a")))
+            .await?
+            .get_revision();
+
+        let mut req = EtcdRangeRequest::new(KeyRange::prefix("50_"));
+        req.set_sort_target(SortTarget::Value);
+        req.set_limit(1);
+        let mut resp = kv.range(req).await?;
+        assert_eq!(resp.count(), 2, "The number of cached keys is wrong");
+        assert!(resp.has_more(), "The limit should leave more keys");
+        assert!(
+            resp.take_header()
+                .unwrap_or_else(|| panic!("Fail to take header from response"))
+                .revision()
+                >= revision.cast(),
+            "The cached range should be up to date with the delete"
+        );
+        let kvs = resp.take_kvs();
+        assert_eq!(kvs.len(), 1, "The limit should be applied");
+        assert_eq!(
+            kvs[0].key_str(),
+            "50_c",
+            "The keys should be sorted by value"
+        );
+
+        let resp = kv.get(EtcdGetRequest::new("50_a")).await?;
+        assert_eq!(resp.count(), 0, "The deleted key should not be cached");
+
+        kv.uncache_range(KeyRange::prefix("50_")).await?;
+        kv.delete(EtcdDeleteRequest::new(KeyRange::prefix("50_")))
+            .await?;
+        Ok(())
+    }
+
+    async fn test_absent_cache(client: &Client) -> Result<()> {
+        let kv = client.kv();
+        // The missing key is cached as absent
+        for _ in 0..2 {
+            let resp = kv.get(EtcdGetRequest::new("49_flag")).await?;
+            assert_eq!(resp.count(), 0, "The key should be absent");
+        }
+        let resp = kv.get_many(vec!["49_flag"]).await?;
+        assert!(resp.get(b"49_flag").is_none(), "The key should be absent");
+
+        // The absent key is updated by the watch once it is put
+        kv.put(EtcdPutRequest::new("49_flag", "on")).await?;
+        let mut resp = kv.get(EtcdGetRequest::new("49_flag")).await?;
+        let kvs = resp.take_kvs();
+        assert_eq!(kvs.len(), 1, "The put key should be cached");
+        assert_eq!(kvs[0].value_str(), "on", "The value of the key is wrong");
+
+        // The deleted key is absent again
+        kv.delete(EtcdDeleteRequest::new(KeyRange::key("49_flag")))
+            .await?;
+        let resp = kv.get(EtcdGetRequest::new("49_flag")).await?;
+        assert_eq!(resp.count(), 0, "The deleted key should be absent");
+        Ok(())
+    }
+
     async fn test_list_prefix(client: &Client) -> Result<()> {
         let prefix = "42_";
         // Add test data to etcd
@@ -655,15 +1376,15 @@ mod tests {
     }
 
     async fn build_etcd_client() -> Result<Client> {
-        let client = Client::connect(ClientConfig {
-            endpoints: vec![
+        let client = Client::connect(ClientConfig::new(
+            vec![
                 DEFAULT_ETCD_ENDPOINT1_FOR_TEST.to_owned(),
                 //DEFAULT_ETCD_ENDPOINT2_FOR_TEST.to_owned(),
             ],
-            auth: None,
-            cache_size: 64,
-            cache_enable: true,
-        })
+            None,
+            64,
+            true,
+        ))
         .await?;
         Ok(client)
     }
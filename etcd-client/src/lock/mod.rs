@@ -3,35 +3,53 @@ mod release;
 /// The mod of lock require operations
 mod require;
 
+use crate::auth::AuthToken;
+use crate::call_options::with_deadline;
+use crate::protos::lock::{LockRequest, UnlockRequest};
 use crate::protos::lock_grpc::LockClient;
 use crate::Result as Res;
 pub use release::{EtcdUnlockRequest, EtcdUnlockResponse};
 pub use require::{EtcdLockRequest, EtcdLockResponse};
+use std::sync::Arc;
 
 /// Lock client.
 #[derive(Clone)]
 pub struct Lock {
     /// Etcd Lock client.
     client: LockClient,
+    /// Auth token attached to RPCs.
+    token: Arc<AuthToken>,
 }
 
 impl Lock {
     /// Creates a new `LockClient`.
     ///
     /// This method should only be called within etcd client.
-    pub(crate) const fn new(client: LockClient) -> Self {
-        Self { client }
+    pub(crate) const fn new(client: LockClient, token: Arc<AuthToken>) -> Self {
+        Self { client, token }
     }
 
     /// Performs a lock operation.
     ///
+    /// It waits until the lock is acquired, set a deadline with
+    /// [`EtcdLockRequest::set_call_options`] to bound the waiting time.
+    ///
     /// # Errors
     ///
-    /// Will return `Err` if RPC call is failed.
+    /// Will return `Err` if RPC call is failed or the deadline is exceeded.
+    /// The call is sent once more if the auth token is expired and refreshed.
     #[inline]
     pub async fn lock(&mut self, req: EtcdLockRequest) -> Res<EtcdLockResponse> {
-        let resp = self.client.lock_async(&req.into())?.await?;
-        Ok(From::from(resp))
+        let options = req.call_options().start();
+        with_deadline(options.deadline, async {
+            let req: LockRequest = req.into();
+            let resp = self
+                .token
+                .call(&options, |option| self.client.lock_async_opt(&req, option))
+                .await?;
+            Ok(From::from(resp))
+        })
+        .await
     }
 
     /// Performs a unlock operation.
@@ -39,9 +57,20 @@ impl Lock {
     /// # Errors
     ///
     /// Will return `Err` if RPC call is failed.
+    /// The call is sent once more if the auth token is expired and refreshed.
     #[inline]
     pub async fn unlock(&mut self, req: EtcdUnlockRequest) -> Res<EtcdUnlockResponse> {
-        let resp = self.client.unlock_async(&req.into())?.await?;
-        Ok(From::from(resp))
+        let options = req.call_options().start();
+        with_deadline(options.deadline, async {
+            let req: UnlockRequest = req.into();
+            let resp = self
+                .token
+                .call(&options, |option| {
+                    self.client.unlock_async_opt(&req, option)
+                })
+                .await?;
+            Ok(From::from(resp))
+        })
+        .await
     }
 }
@@ -1,10 +1,14 @@
+use crate::namespace;
 use crate::protos::lock::{UnlockRequest, UnlockResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 
 /// Request for requiring a lock
 pub struct EtcdUnlockRequest {
     /// Etcd lock request
     proto: UnlockRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdUnlockRequest {
@@ -21,6 +25,7 @@ impl EtcdUnlockRequest {
 
         Self {
             proto: lock_request,
+            options: CallOptions::default(),
         }
     }
 
@@ -29,6 +34,23 @@ impl EtcdUnlockRequest {
     pub fn get_key(&self) -> Vec<u8> {
         self.proto.get_key().to_vec()
     }
+
+    /// Prepends the namespace prefix to the lock key.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        namespace::prefix_key(prefix, &mut self.proto.key);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdUnlockRequest> for UnlockRequest {
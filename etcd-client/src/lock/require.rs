@@ -1,4 +1,6 @@
+use crate::namespace;
 use crate::protos::lock::{LockRequest, LockResponse};
+use crate::CallOptions;
 use crate::ResponseHeader;
 use clippy_utilities::Cast;
 
@@ -6,6 +8,8 @@ use clippy_utilities::Cast;
 pub struct EtcdLockRequest {
     /// Etcd lock request
     proto: LockRequest,
+    /// Per-request options.
+    options: CallOptions,
 }
 
 impl EtcdLockRequest {
@@ -23,6 +27,7 @@ impl EtcdLockRequest {
 
         Self {
             proto: lock_request,
+            options: CallOptions::default(),
         }
     }
 
@@ -37,6 +42,23 @@ impl EtcdLockRequest {
     pub fn get_lease(&self) -> u64 {
         self.proto.get_lease().cast()
     }
+
+    /// Prepends the namespace prefix to the lock name.
+    pub(crate) fn prefix_keys(&mut self, prefix: &[u8]) {
+        namespace::prefix_key(prefix, &mut self.proto.name);
+    }
+
+    /// Sets the per-request options, such as the deadline of the request.
+    #[inline]
+    pub fn set_call_options(&mut self, options: CallOptions) {
+        self.options = options;
+    }
+
+    /// Gets the per-request options.
+    #[inline]
+    pub const fn call_options(&self) -> &CallOptions {
+        &self.options
+    }
 }
 
 impl From<EtcdLockRequest> for LockRequest {
@@ -65,6 +87,11 @@ impl EtcdLockResponse {
     pub fn take_key(&mut self) -> Vec<u8> {
         self.proto.take_key()
     }
+
+    /// Strips the namespace prefix from the lock key.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        namespace::strip_key(prefix, &mut self.proto.key);
+    }
 }
 
 impl From<LockResponse> for EtcdLockResponse {
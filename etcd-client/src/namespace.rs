@@ -0,0 +1,324 @@
+//! Namespaced views of the client, which keep all the keys under a prefix.
+//!
+//! The prefix is prepended to the keys of the requests, including the key ranges and the
+//! compares of txns, and stripped from the keys of the responses and the watch events, so
+//! the users of a namespace see the keys relative to the prefix. A key range ending with
+//! `\0`, such as [`KeyRange::all`], is limited to the keys under the prefix.
+
+use std::sync::Arc;
+
+use crate::watch::SingleWatchEventReceiver;
+use crate::{
+    Client, EtcdDeleteRequest, EtcdDeleteResponse, EtcdGetRequest, EtcdGetResponse,
+    EtcdLockRequest, EtcdLockResponse, EtcdPutRequest, EtcdPutResponse, EtcdRangeRequest,
+    EtcdRangeResponse, EtcdTxnRequest, EtcdTxnResponse, EtcdUnlockRequest, EtcdUnlockResponse,
+    EtcdWatchResponse, KeyRange, Kv, Lease, Lock, Result,
+};
+
+/// Prepends the prefix to a key.
+pub(crate) fn prefix_key(prefix: &[u8], key: &mut Vec<u8>) {
+    let mut prefixed = prefix.to_vec();
+    prefixed.append(key);
+    *key = prefixed;
+}
+
+/// Prepends the prefix to a key range. The range end `\0`, which means all the keys
+/// no less than the key, is replaced by the end of the prefix.
+pub(crate) fn prefix_key_range(prefix: &[u8], key: &mut Vec<u8>, range_end: &mut Vec<u8>) {
+    prefix_key(prefix, key);
+    if range_end.as_slice() == [0] {
+        *range_end = KeyRange::prefix(prefix).take_range_end();
+    } else if !range_end.is_empty() {
+        prefix_key(prefix, range_end);
+    }
+}
+
+/// Strips the prefix from a key, the key is left unchanged if it is not under the prefix.
+pub(crate) fn strip_key(prefix: &[u8], key: &mut Vec<u8>) {
+    if key.starts_with(prefix) {
+        *key = key.split_off(prefix.len());
+    }
+}
+
+/// A namespaced view of the client.
+#[derive(Clone)]
+pub struct Namespace {
+    /// The client.
+    client: Client,
+    /// The prefix of the namespace.
+    prefix: Arc<[u8]>,
+}
+
+impl Namespace {
+    /// Creates a new `Namespace` of the client.
+    pub(crate) fn new(client: Client, prefix: Vec<u8>) -> Self {
+        Self {
+            client,
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Gets the prefix of the namespace.
+    #[inline]
+    #[must_use]
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Gets a namespaced key-value client.
+    #[inline]
+    #[must_use]
+    pub fn kv(&self) -> NamespaceKv {
+        NamespaceKv {
+            kv: self.client.kv(),
+            prefix: Arc::clone(&self.prefix),
+        }
+    }
+
+    /// Gets a namespaced watch client.
+    #[inline]
+    #[must_use]
+    pub fn watch(&self) -> NamespaceWatch {
+        NamespaceWatch {
+            client: self.client.clone(),
+            prefix: Arc::clone(&self.prefix),
+        }
+    }
+
+    /// Gets a namespaced lock client.
+    #[inline]
+    #[must_use]
+    pub fn lock(&self) -> NamespaceLock {
+        NamespaceLock {
+            lock: self.client.lock(),
+            prefix: Arc::clone(&self.prefix),
+        }
+    }
+
+    /// Gets a lease client, leases are not namespaced as they have no keys.
+    #[inline]
+    #[must_use]
+    pub fn lease(&self) -> Lease {
+        self.client.lease()
+    }
+}
+
+/// A namespaced key-value client.
+#[derive(Clone)]
+pub struct NamespaceKv {
+    /// The key-value client.
+    kv: Arc<Kv>,
+    /// The prefix of the namespace.
+    prefix: Arc<[u8]>,
+}
+
+impl NamespaceKv {
+    /// Performs a key-value saving operation in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn put(&self, mut req: EtcdPutRequest) -> Result<EtcdPutResponse> {
+        req.prefix_keys(&self.prefix);
+        let mut resp = self.kv.put(req).await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+
+    /// Performs a single key-value fetching operation in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn get(&self, mut req: EtcdGetRequest) -> Result<EtcdGetResponse> {
+        req.prefix_keys(&self.prefix);
+        let mut resp = self.kv.get(req).await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+
+    /// Performs a range key-value fetching operation in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn range(&self, mut req: EtcdRangeRequest) -> Result<EtcdRangeResponse> {
+        req.prefix_keys(&self.prefix);
+        let mut resp = self.kv.range(req).await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+
+    /// Performs a key-value deleting operation in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn delete(&self, mut req: EtcdDeleteRequest) -> Result<EtcdDeleteResponse> {
+        req.prefix_keys(&self.prefix);
+        let mut resp = self.kv.delete(req).await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+
+    /// Performs a transaction operation in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn txn(&self, mut req: EtcdTxnRequest) -> Result<EtcdTxnResponse> {
+        req.prefix_keys(&self.prefix);
+        let mut resp = self.kv.txn(req).await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+}
+
+/// A namespaced watch client.
+#[derive(Clone)]
+pub struct NamespaceWatch {
+    /// The client.
+    client: Client,
+    /// The prefix of the namespace.
+    prefix: Arc<[u8]>,
+}
+
+impl NamespaceWatch {
+    /// Watches a key range in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client is closed.
+    #[inline]
+    pub async fn watch(&self, mut key_range: KeyRange) -> Result<NamespaceWatchReceiver> {
+        let mut key = key_range.take_key();
+        let mut range_end = key_range.take_range_end();
+        prefix_key_range(&self.prefix, &mut key, &mut range_end);
+        Ok(NamespaceWatchReceiver {
+            inner: self.client.watch(KeyRange::range(key, range_end)).await?,
+            prefix: Arc::clone(&self.prefix),
+        })
+    }
+}
+
+/// The receiver of the watch responses of a namespaced watch.
+pub struct NamespaceWatchReceiver {
+    /// The receiver of the watch.
+    inner: SingleWatchEventReceiver,
+    /// The prefix of the namespace.
+    prefix: Arc<[u8]>,
+}
+
+impl NamespaceWatchReceiver {
+    /// Receives a watch response, with the keys relative to the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the client is closed.
+    #[inline]
+    pub async fn recv(&mut self) -> Result<EtcdWatchResponse> {
+        let mut resp = self.inner.recv().await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+}
+
+/// A namespaced lock client.
+#[derive(Clone)]
+pub struct NamespaceLock {
+    /// The lock client.
+    lock: Lock,
+    /// The prefix of the namespace.
+    prefix: Arc<[u8]>,
+}
+
+impl NamespaceLock {
+    /// Performs a lock operation in the namespace.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed or the deadline is exceeded.
+    #[inline]
+    pub async fn lock(&mut self, mut req: EtcdLockRequest) -> Result<EtcdLockResponse> {
+        req.prefix_keys(&self.prefix);
+        let mut resp = self.lock.lock(req).await?;
+        resp.strip_key_prefix(&self.prefix);
+        Ok(resp)
+    }
+
+    /// Performs an unlock operation in the namespace, `req` should have the key returned
+    /// by [`NamespaceLock::lock`].
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn unlock(&mut self, mut req: EtcdUnlockRequest) -> Result<EtcdUnlockResponse> {
+        req.prefix_keys(&self.prefix);
+        self.lock.unlock(req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_keys() {
+        let prefix = b"tenant/".as_slice();
+        let prefixed = |mut key_range: KeyRange| {
+            let mut key = key_range.take_key();
+            let mut range_end = key_range.take_range_end();
+            prefix_key_range(prefix, &mut key, &mut range_end);
+            (key, range_end)
+        };
+
+        assert_eq!(
+            prefixed(KeyRange::key("foo")),
+            (b"tenant/foo".to_vec(), vec![]),
+            "A single key should be prefixed"
+        );
+        assert_eq!(
+            prefixed(KeyRange::prefix("foo")),
+            (b"tenant/foo".to_vec(), b"tenant/fop".to_vec()),
+            "A prefix range should be prefixed"
+        );
+        assert_eq!(
+            prefixed(KeyRange::all()),
+            (b"tenant/\0".to_vec(), b"tenant0".to_vec()),
+            "All the keys should be limited in the namespace"
+        );
+
+        // A prefix with an embedded 0xff byte
+        let mut key = b"foo".to_vec();
+        let mut range_end = vec![0];
+        prefix_key_range(b"t\xff1/", &mut key, &mut range_end);
+        assert_eq!(
+            (key, range_end),
+            (b"t\xff1/foo".to_vec(), b"t\xff10".to_vec()),
+            "The end of the prefix should keep the embedded 0xff byte"
+        );
+        assert_eq!(
+            KeyRange::prefix(b"a\xff\xff".to_vec()).take_range_end(),
+            b"b".to_vec(),
+            "The trailing 0xff bytes should be trimmed"
+        );
+        assert_eq!(
+            KeyRange::prefix(b"\xff".to_vec()).take_range_end(),
+            vec![0],
+            "A prefix of all 0xff bytes should end with all the keys"
+        );
+
+        let mut key = b"tenant/foo".to_vec();
+        strip_key(prefix, &mut key);
+        assert_eq!(key, b"foo".to_vec(), "The prefix should be stripped");
+        let mut key = b"other/foo".to_vec();
+        strip_key(prefix, &mut key);
+        assert_eq!(key, b"other/foo".to_vec(), "Other keys should be unchanged");
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use backoff::backoff::Backoff;
+use backoff::future::Retry;
+use backoff::ExponentialBackoff;
+use futures::TryFutureExt;
+use smol::Timer;
+
+use crate::call_options::with_deadline;
+use crate::{EtcdError, NoopNotify, OverflowArithmetic, Result, SmolSleeper};
+
+/// The default initial retry interval
+const DEFAULT_INITIAL_INTERVAL: Duration = Duration::from_secs(1);
+/// The default max retry interval
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(60);
+/// The default multiplier of retry interval
+const DEFAULT_MULTIPLIER: f64 = 1.5;
+/// The default randomization factor of retry interval
+const DEFAULT_JITTER: f64 = 0.5;
+/// The default max elapsed time of retrying
+const DEFAULT_MAX_ELAPSED_TIME: Duration = Duration::from_secs(10);
+
+/// The operations whose retry policy can be overridden.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryOperation {
+    /// `Kv::put`
+    Put,
+    /// `Kv::get`
+    Get,
+    /// `Kv::range`
+    Range,
+    /// `Kv::delete`
+    Delete,
+    /// `Kv::txn`
+    Txn,
+    /// `Kv::compact`
+    Compact,
+    /// The commits of `Kv::stm` which conflict with other writes
+    Stm,
+    /// The commits of `Kv::update` which conflict with other writes
+    Update,
+    /// `Lease::grant`
+    LeaseGrant,
+    /// `Lease::revoke`
+    LeaseRevoke,
+    /// `Auth::authenticate`
+    Authenticate,
+}
+
+/// Retry policy with exponential backoff.
+///
+/// Only the errors classified as retryable by [`EtcdError::is_retryable`](crate::EtcdError::is_retryable)
+/// are retried, others are returned immediately.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The interval before the first retry.
+    pub initial_interval: Duration,
+    /// The max interval between two retries.
+    pub max_interval: Duration,
+    /// The interval is multiplied by `multiplier` after each retry.
+    pub multiplier: f64,
+    /// The randomization factor of intervals, the interval is randomly picked in
+    /// `[interval * (1 - jitter), interval * (1 + jitter)]`.
+    pub jitter: f64,
+    /// Stop retrying after `max_elapsed_time`, `None` means never stop.
+    pub max_elapsed_time: Option<Duration>,
+    /// The max number of attempts including the first one, `None` means no limit.
+    pub max_attempts: Option<usize>,
+    /// Per-operation policies overriding this policy.
+    overrides: HashMap<RetryOperation, RetryPolicy>,
+}
+
+impl Default for RetryPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            initial_interval: DEFAULT_INITIAL_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            multiplier: DEFAULT_MULTIPLIER,
+            jitter: DEFAULT_JITTER,
+            max_elapsed_time: Some(DEFAULT_MAX_ELAPSED_TIME),
+            max_attempts: None,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// New a retry policy with default backoff parameters
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// New a retry policy which never retries
+    #[must_use]
+    #[inline]
+    pub fn no_retry() -> Self {
+        Self::default().with_max_attempts(Some(1))
+    }
+
+    /// Set the interval before the first retry
+    #[must_use]
+    #[inline]
+    pub const fn with_initial_interval(mut self, initial_interval: Duration) -> Self {
+        self.initial_interval = initial_interval;
+        self
+    }
+
+    /// Set the max interval between two retries
+    #[must_use]
+    #[inline]
+    pub const fn with_max_interval(mut self, max_interval: Duration) -> Self {
+        self.max_interval = max_interval;
+        self
+    }
+
+    /// Set the multiplier of the interval
+    #[must_use]
+    #[inline]
+    pub const fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Set the randomization factor of intervals
+    #[must_use]
+    #[inline]
+    pub const fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the max elapsed time of retrying
+    #[must_use]
+    #[inline]
+    pub const fn with_max_elapsed_time(mut self, max_elapsed_time: Option<Duration>) -> Self {
+        self.max_elapsed_time = max_elapsed_time;
+        self
+    }
+
+    /// Set the max number of attempts
+    #[must_use]
+    #[inline]
+    pub const fn with_max_attempts(mut self, max_attempts: Option<usize>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Override the policy of an operation
+    #[must_use]
+    #[inline]
+    pub fn with_override(mut self, operation: RetryOperation, policy: Self) -> Self {
+        self.overrides.insert(operation, policy);
+        self
+    }
+
+    /// Get the policy of an operation
+    #[must_use]
+    #[inline]
+    pub fn for_operation(&self, operation: RetryOperation) -> &Self {
+        self.overrides.get(&operation).unwrap_or(self)
+    }
+
+    /// Build the exponential backoff
+    fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoff {
+            current_interval: self.initial_interval,
+            initial_interval: self.initial_interval,
+            randomization_factor: self.jitter,
+            multiplier: self.multiplier,
+            max_interval: self.max_interval,
+            max_elapsed_time: self.max_elapsed_time,
+            ..ExponentialBackoff::default()
+        }
+    }
+
+    /// Run `operation` and retry it according to the policy of `retry_operation`,
+    /// gives up when the `deadline` expires.
+    pub(crate) async fn retry<T, F, Fut>(
+        &self,
+        retry_operation: RetryOperation,
+        deadline: Option<Instant>,
+        mut operation: F,
+    ) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let policy = self.for_operation(retry_operation);
+        let max_attempts = policy.max_attempts;
+        let mut attempts = 0_usize;
+        let retry = Retry::new(SmolSleeper, policy.backoff(), NoopNotify, || {
+            attempts = attempts.overflow_add(1);
+            let exhausted = max_attempts.map_or(false, |max| attempts >= max);
+            operation().map_err(move |e| {
+                if exhausted || !e.is_retryable() {
+                    backoff::Error::Permanent(e)
+                } else {
+                    backoff::Error::Transient(e)
+                }
+            })
+        });
+        with_deadline(deadline, retry).await
+    }
+}
+
+/// Backoff between the attempts of an optimistic transaction,
+/// which is retried when it conflicts with other writes.
+pub(crate) struct ConflictBackoff {
+    /// The backoff of the retry policy.
+    backoff: ExponentialBackoff,
+    /// The number of attempts so far.
+    attempts: usize,
+    /// The max number of attempts of the retry policy.
+    max_attempts: Option<usize>,
+}
+
+impl ConflictBackoff {
+    /// Creates a new `ConflictBackoff` following `policy`.
+    pub(crate) fn new(policy: &RetryPolicy) -> Self {
+        Self {
+            backoff: policy.backoff(),
+            attempts: 0,
+            max_attempts: policy.max_attempts,
+        }
+    }
+
+    /// Records a conflicted attempt and waits before the next one.
+    ///
+    /// Returns `EtcdError::TxnConflict` if the policy gives up.
+    pub(crate) async fn conflicted(&mut self) -> Result<()> {
+        self.attempts = self.attempts.overflow_add(1);
+        let exhausted = self.max_attempts.map_or(false, |max| self.attempts >= max);
+        match self.backoff.next_backoff() {
+            Some(interval) if !exhausted => {
+                Timer::after(interval).await;
+                Ok(())
+            }
+            _ => Err(EtcdError::TxnConflict(self.attempts)),
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grpcio::{RpcStatus, RpcStatusCode};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn rpc_error(code: RpcStatusCode) -> EtcdError {
+        grpcio::Error::RpcFailure(RpcStatus::new(code)).into()
+    }
+
+    fn run(policy: &RetryPolicy, operation: RetryOperation, code: RpcStatusCode) -> usize {
+        let calls = AtomicUsize::new(0);
+        let res: Result<()> = smol::block_on(policy.retry(operation, None, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(rpc_error(code))
+        }));
+        assert!(res.is_err(), "The operation should fail");
+        calls.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::new()
+            .with_initial_interval(Duration::from_millis(1))
+            .with_max_interval(Duration::from_millis(1))
+            .with_max_attempts(Some(3))
+            .with_override(RetryOperation::Txn, RetryPolicy::no_retry());
+
+        assert_eq!(
+            run(&policy, RetryOperation::Put, RpcStatusCode::UNAVAILABLE),
+            3,
+            "Retryable error should be retried until the max attempts"
+        );
+        assert_eq!(
+            run(
+                &policy,
+                RetryOperation::Put,
+                RpcStatusCode::PERMISSION_DENIED
+            ),
+            1,
+            "Fatal error should not be retried"
+        );
+        assert_eq!(
+            run(
+                &policy,
+                RetryOperation::Put,
+                RpcStatusCode::INVALID_ARGUMENT
+            ),
+            1,
+            "Fatal error should not be retried"
+        );
+        assert_eq!(
+            run(&policy, RetryOperation::Txn, RpcStatusCode::UNAVAILABLE),
+            1,
+            "Overridden policy should not retry"
+        );
+    }
+}
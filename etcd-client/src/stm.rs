@@ -0,0 +1,263 @@
+//! Software transactional memory over etcd transactions.
+//!
+//! The user function reads and writes through a [`StmTxn`], the writes are buffered and
+//! committed in a single transaction guarded by the mod revisions of the reads. If other
+//! writers modify the keys read in between, the commit fails and the function is run
+//! again from scratch, so the function should have no side effects other than the `StmTxn`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+
+use clippy_utilities::Cast;
+
+use crate::kv::DEFAULT_MAX_TXN_OPS;
+use crate::retry::ConflictBackoff;
+use crate::{
+    EtcdDeleteRequest, EtcdError, EtcdKeyValue, EtcdPutRequest, EtcdRangeRequest, EtcdTxnRequest,
+    KeyRange, Kv, OverflowArithmetic, Result, RetryPolicy, TxnCmp,
+};
+
+/// The isolation level of a software transaction.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    /// All the reads are from the revision of the first read, and the commit fails if any
+    /// key read or written is modified after that revision.
+    SerializableSnapshot,
+    /// All the reads are from the revision of the first read, and the commit fails if any
+    /// key read is modified after it is read.
+    Serializable,
+    /// A key is read once and cached in the transaction, and the commit fails if any
+    /// key read is modified after it is read.
+    RepeatableReads,
+    /// The reads are not checked when committing.
+    ReadCommitted,
+}
+
+/// The future returned by the function of a software transaction.
+pub type StmFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// The transactional view passed to the function of a software transaction.
+pub struct StmTxn<'a> {
+    /// The kv client to read and commit.
+    kv: &'a Kv,
+    /// The isolation level.
+    isolation: Isolation,
+    /// The revision the reads are pinned to, set by the first read
+    /// in the serializable isolation levels.
+    revision: Option<usize>,
+    /// The keys read and their key-value pairs, `None` if the key doesn't exist.
+    read_set: HashMap<Vec<u8>, Option<EtcdKeyValue>>,
+    /// The ranges read and the revisions they are read at.
+    range_reads: Vec<(KeyRange, usize)>,
+    /// The buffered writes, `None` means to delete the key.
+    write_set: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> StmTxn<'a> {
+    /// Creates a new `StmTxn` with empty read and write sets.
+    fn new(kv: &'a Kv, isolation: Isolation) -> Self {
+        Self {
+            kv,
+            isolation,
+            revision: None,
+            read_set: HashMap::new(),
+            range_reads: Vec::new(),
+            write_set: BTreeMap::new(),
+        }
+    }
+
+    /// Gets the value of a key, the buffered writes of this transaction are visible.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn get<K>(&mut self, key: K) -> Result<Option<Vec<u8>>>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let key = key.into();
+        if let Some(value) = self.write_set.get(&key) {
+            return Ok(value.clone());
+        }
+        if self.isolation != Isolation::ReadCommitted {
+            if let Some(kv) = self.read_set.get(&key) {
+                return Ok(kv.as_ref().map(|kv| kv.value().to_vec()));
+            }
+        }
+
+        let (mut kvs, _revision) = self.fetch(KeyRange::key(key.clone())).await?;
+        let kv = kvs.pop();
+        let value = kv.as_ref().map(|kv| kv.value().to_vec());
+        if self.isolation != Isolation::ReadCommitted {
+            let _prev = self.read_set.insert(key, kv);
+        }
+        Ok(value)
+    }
+
+    /// Gets the key-value pairs in a key range ordered by key,
+    /// the buffered writes of this transaction are visible.
+    ///
+    /// Besides the keys read, the commit also fails if keys are created in the range.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if RPC call is failed.
+    #[inline]
+    pub async fn range(&mut self, key_range: KeyRange) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (kvs, revision) = self.fetch(key_range.clone()).await?;
+        let mut pairs = kvs
+            .iter()
+            .map(|kv| (kv.key().to_vec(), Some(kv.value().to_vec())))
+            .collect::<BTreeMap<_, _>>();
+        for (key, value) in &self.write_set {
+            if key_range.contains(key) {
+                let _prev = pairs.insert(key.clone(), value.clone());
+            }
+        }
+
+        if self.isolation != Isolation::ReadCommitted {
+            for kv in kvs {
+                let _prev = self.read_set.insert(kv.key().to_vec(), Some(kv));
+            }
+            self.range_reads.push((key_range, revision));
+        }
+        Ok(pairs
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect())
+    }
+
+    /// Puts a key-value pair, which is written when the transaction commits.
+    #[inline]
+    pub fn put<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<Vec<u8>>,
+        V: Into<Vec<u8>>,
+    {
+        let _prev = self.write_set.insert(key.into(), Some(value.into()));
+    }
+
+    /// Deletes a key, which is deleted when the transaction commits.
+    #[inline]
+    pub fn delete<K>(&mut self, key: K)
+    where
+        K: Into<Vec<u8>>,
+    {
+        let _prev = self.write_set.insert(key.into(), None);
+    }
+
+    /// Reads a key range, returns the key-value pairs and the revision they are read at.
+    /// The reads are pinned to the revision of the first read in the serializable
+    /// isolation levels.
+    async fn fetch(&mut self, key_range: KeyRange) -> Result<(Vec<EtcdKeyValue>, usize)> {
+        let serializable = matches!(
+            self.isolation,
+            Isolation::Serializable | Isolation::SerializableSnapshot
+        );
+        let mut req = EtcdRangeRequest::new(key_range);
+        if let (true, Some(revision)) = (serializable, self.revision) {
+            req.set_revision(revision);
+            let mut resp = self.kv.range_rpc(req).await?;
+            return Ok((resp.take_kvs(), revision));
+        }
+        let mut resp = self.kv.range_rpc(req).await?;
+        let revision: usize = resp
+            .take_header()
+            .ok_or_else(|| EtcdError::InternalError("range response has no header".to_owned()))?
+            .revision()
+            .cast();
+        if serializable {
+            self.revision = Some(revision);
+        }
+        Ok((resp.take_kvs(), revision))
+    }
+
+    /// Gets the number of compares guarding the commit.
+    fn compare_count(&self) -> usize {
+        let mut count = 0_usize;
+        if self.isolation != Isolation::ReadCommitted {
+            count = self.read_set.len().overflow_add(self.range_reads.len());
+        }
+        if let (Isolation::SerializableSnapshot, Some(_)) = (self.isolation, self.revision) {
+            count = count.overflow_add(self.write_set.len());
+        }
+        count
+    }
+
+    /// Commits the buffered writes, returns `false` if the transaction conflicts with
+    /// other writes. After the commit, it waits until the cache reflects the writes.
+    ///
+    /// The compares and the writes should not exceed the default `--max-txn-ops` of etcd,
+    /// otherwise [`EtcdError::TxnOpsExceeded`] is returned without sending the txn.
+    async fn commit(self) -> Result<bool> {
+        let ops = self.compare_count().max(self.write_set.len());
+        if ops > DEFAULT_MAX_TXN_OPS {
+            return Err(EtcdError::TxnOpsExceeded(ops, DEFAULT_MAX_TXN_OPS));
+        }
+        let mut txn = EtcdTxnRequest::new();
+        if self.isolation != Isolation::ReadCommitted {
+            for (key, kv) in self.read_set {
+                let mod_revision = kv.as_ref().map_or(0, EtcdKeyValue::mod_revision);
+                txn = txn.when_mod_revision(KeyRange::key(key), TxnCmp::Equal, mod_revision);
+            }
+            for (key_range, revision) in self.range_reads {
+                txn = txn.when_mod_revision(key_range, TxnCmp::Less, revision.overflow_add(1));
+            }
+        }
+        if let (Isolation::SerializableSnapshot, Some(revision)) = (self.isolation, self.revision) {
+            for key in self.write_set.keys() {
+                txn = txn.when_mod_revision(
+                    KeyRange::key(key.clone()),
+                    TxnCmp::Less,
+                    revision.overflow_add(1),
+                );
+            }
+        }
+        let keys = self.write_set.keys().cloned().collect::<Vec<_>>();
+        for (key, value) in self.write_set {
+            txn = match value {
+                Some(value) => txn.and_then(EtcdPutRequest::new(key, value)),
+                None => txn.and_then(EtcdDeleteRequest::new(KeyRange::key(key))),
+            };
+        }
+        let mut resp = self.kv.txn(txn).await?;
+        if !resp.is_success() {
+            return Ok(false);
+        }
+        let revision: usize = resp
+            .take_header()
+            .ok_or_else(|| EtcdError::InternalError("txn response has no header".to_owned()))?
+            .revision()
+            .cast();
+        // Wait until cache is updated, the same as the single put and delete
+        for key in &keys {
+            self.kv.wait_cache_updated(key, revision.cast(), None).await;
+        }
+        Ok(true)
+    }
+}
+
+/// Runs `f` in a software transaction and commits its writes,
+/// `f` is run again if the commit conflicts with other writes.
+pub(crate) async fn run<'k, T, F>(
+    kv: &'k Kv,
+    policy: &RetryPolicy,
+    isolation: Isolation,
+    mut f: F,
+) -> Result<T>
+where
+    F: for<'s> FnMut(&'s mut StmTxn<'k>) -> StmFuture<'s, T>,
+{
+    let mut backoff = ConflictBackoff::new(policy);
+    loop {
+        let mut txn = StmTxn::new(kv, isolation);
+        let value = f(&mut txn).await?;
+        if txn.commit().await? {
+            return Ok(value);
+        }
+        backoff.conflicted().await?;
+    }
+}
@@ -0,0 +1,142 @@
+use clippy_utilities::Cast;
+use futures::stream::StreamExt;
+use futures::SinkExt;
+use grpcio::WriteFlags;
+
+use super::EtcdWatchRequest;
+use crate::auth::AuthToken;
+use crate::call_options::with_deadline;
+use crate::protos::kv::Event_EventType;
+use crate::protos::rpc_grpc::WatchClient;
+use crate::{CallOptions, EtcdError, EtcdKeyValue, KeyRange, Result};
+
+/// A version of a key in its history, which is either a put or a deletion.
+#[derive(Clone)]
+pub struct KeyVersion {
+    /// The revision the key is modified at.
+    revision: usize,
+    /// The key-value pair put, `None` if the key is deleted.
+    kv: Option<EtcdKeyValue>,
+}
+
+impl KeyVersion {
+    /// Gets the revision the key is modified at.
+    #[inline]
+    #[must_use]
+    pub const fn revision(&self) -> usize {
+        self.revision
+    }
+
+    /// Gets the key-value pair put, `None` if the key is deleted.
+    #[inline]
+    #[must_use]
+    pub const fn kv(&self) -> Option<&EtcdKeyValue> {
+        self.kv.as_ref()
+    }
+
+    /// Returns `true` if the key is deleted at the revision.
+    #[inline]
+    #[must_use]
+    pub const fn is_deleted(&self) -> bool {
+        self.kv.is_none()
+    }
+
+    /// Takes the key-value pair out, leaving a `None` in its place.
+    #[inline]
+    pub fn take_kv(&mut self) -> Option<EtcdKeyValue> {
+        self.kv.take()
+    }
+}
+
+/// The max number of revisions etcd sends in a response to a watch catching up the history,
+/// `watchBatchMaxRevs` of etcd. A response of so many events may be followed by more.
+const WATCH_BATCH_MAX_REVS: usize = 1000;
+
+/// Replays the modifications of `key` in `[from, to]` through a dedicated watch stream,
+/// `to` should not be greater than the current revision.
+///
+/// The replay ends at the first event at or after `to`, or at the first response of
+/// events whose header revision reaches `to`, as the server sends all the history up to
+/// the header revision in a response unless it is batched. If the key has no modification
+/// since `from`, the server sends no event, then the replay ends at the progress
+/// notification requested after the watch is created, which etcd before 3.5.8 doesn't
+/// send, so it is bounded by the deadline of `options`.
+pub(crate) async fn key_history(
+    watch_client: &WatchClient,
+    token: &AuthToken,
+    key: Vec<u8>,
+    from: usize,
+    to: usize,
+    options: &CallOptions,
+) -> Result<Vec<KeyVersion>> {
+    with_deadline(
+        options.deadline,
+        replay(watch_client, token, key, from, to, options),
+    )
+    .await
+}
+
+/// Replays the history without the deadline, see `key_history`.
+async fn replay(
+    watch_client: &WatchClient,
+    token: &AuthToken,
+    key: Vec<u8>,
+    from: usize,
+    to: usize,
+    options: &CallOptions,
+) -> Result<Vec<KeyVersion>> {
+    let (mut req_sender, mut resp_receiver) =
+        watch_client.watch_opt(token.call_option(options)?)?;
+    let mut req = EtcdWatchRequest::create(KeyRange::key(key));
+    req.set_start_revision(from);
+    req_sender.send((req.into(), WriteFlags::default())).await?;
+
+    let mut versions = Vec::new();
+    'replay: while let Some(resp) = resp_receiver.next().await {
+        let mut resp = resp?;
+        if resp.get_compact_revision() > 0 {
+            return Err(EtcdError::Compacted(Some(resp.get_compact_revision())));
+        }
+        if resp.get_canceled() {
+            return Err(EtcdError::InternalError(format!(
+                "history watch is canceled, the reason is: {}",
+                resp.get_cancel_reason()
+            )));
+        }
+        if resp.get_created() {
+            req_sender
+                .send((EtcdWatchRequest::progress().into(), WriteFlags::default()))
+                .await?;
+            continue;
+        }
+
+        let header_revision: usize = resp.get_header().get_revision().cast();
+        let events = resp.take_events();
+        if events.is_empty() {
+            // The progress notification, all the history is delivered
+            break;
+        }
+        let batched = events.len() >= WATCH_BATCH_MAX_REVS;
+        for mut event in events {
+            let kv = event.take_kv();
+            let revision: usize = kv.get_mod_revision().cast();
+            if revision > to {
+                break 'replay;
+            }
+            let kv = match event.get_field_type() {
+                Event_EventType::PUT => Some(EtcdKeyValue::from(kv)),
+                Event_EventType::DELETE => None,
+            };
+            versions.push(KeyVersion { revision, kv });
+            if revision == to {
+                break 'replay;
+            }
+        }
+        if header_revision >= to && !batched {
+            break;
+        }
+    }
+    // Dropping the stream cancels the watch
+    drop(req_sender);
+    Ok(versions)
+}
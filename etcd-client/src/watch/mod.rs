@@ -54,16 +54,22 @@ use grpcio::{ClientDuplexReceiver, StreamingCallSink, WriteFlags};
 use smol::channel::{unbounded, Receiver, Sender};
 use smol::Task;
 
+pub(crate) use history::key_history;
+pub use history::KeyVersion;
 pub use watch_impl::{EtcdWatchRequest, EtcdWatchResponse};
 
+use crate::auth::AuthToken;
 use crate::lazy::Lazy;
 use crate::protos::kv;
 use crate::protos::rpc::{WatchRequest, WatchResponse};
 use crate::protos::rpc_grpc::WatchClient;
+use crate::CallOptions;
 use crate::KeyRange;
 use crate::Result;
 use crate::{EtcdError, EtcdKeyValue};
 
+/// Key history mod for replaying the modifications of a key.
+mod history;
 /// Watch implementation mod.
 mod watch_impl;
 
@@ -501,7 +507,7 @@ impl WatchTunnel {
     }
 
     /// Creates a new `WatchClient`.
-    fn new(client: &WatchClient) -> Self {
+    fn new(client: &WatchClient, token: &AuthToken) -> Self {
         let (watch_req_sender, watch_req_receiver) = new_watch_request_chan();
         let (cancel_req_sender, cancel_req_receiver) = new_cancel_request_chan();
         // From recv loop to send loop, notify a watch request is done, next watch can be excuted.
@@ -510,8 +516,9 @@ impl WatchTunnel {
         let (shutdown_tx, shutdown_rx) = new_shutdown_chan();
         let shutdown_response = shutdown_rx.clone();
         // Monitor inbound watch response and transfer to the receiver
-        let (client_req_sender, client_resp_receiver) = client
-            .watch()
+        let (client_req_sender, client_resp_receiver) = token
+            .call_option(&CallOptions::default())
+            .and_then(|option| client.watch_opt(option))
             .unwrap_or_else(|e| panic!("failed to send watch command, the error is: {}", e));
 
         let shared = Arc::new(WatchTunnelShared::new(cancel_req_sender, shutdown_tx));
@@ -633,8 +640,8 @@ pub struct Watch {
 
 impl Watch {
     /// Create a new `WatchClient`.
-    pub(crate) fn new(client: &WatchClient) -> Self {
-        let tunnel = Arc::new(WatchTunnel::new(client));
+    pub(crate) fn new(client: &WatchClient, token: &AuthToken) -> Self {
+        let tunnel = Arc::new(WatchTunnel::new(client, token));
 
         Self { tunnel }
     }
@@ -1,6 +1,7 @@
+use crate::namespace;
 use crate::protos::rpc::{
-    WatchCancelRequest, WatchCreateRequest, WatchRequest, WatchRequest_oneof_request_union,
-    WatchResponse,
+    WatchCancelRequest, WatchCreateRequest, WatchProgressRequest, WatchRequest,
+    WatchRequest_oneof_request_union, WatchResponse,
 };
 use crate::Event;
 use crate::KeyRange;
@@ -50,6 +51,18 @@ impl EtcdWatchRequest {
         }
     }
 
+    /// Creates a new `WatchRequest` which requests a progress notification,
+    /// the server responds with the current revision once the watchers are synced.
+    #[inline]
+    #[must_use]
+    pub fn progress() -> Self {
+        let mut watch_request = WatchRequest::new();
+        watch_request.set_progress_request(WatchProgressRequest::new());
+        Self {
+            proto: watch_request,
+        }
+    }
+
     /// Sets the revision to watch from (inclusive). No `start_revision` is "now".
     /// It only effects when the request is for subscribing.
     #[inline]
@@ -152,6 +165,18 @@ impl EtcdWatchResponse {
 
         events.into_iter().map(From::from).collect()
     }
+
+    /// Strips the namespace prefix from the keys of the events.
+    pub(crate) fn strip_key_prefix(&mut self, prefix: &[u8]) {
+        for event in &mut self.proto.events {
+            if let Some(kv) = event.kv.as_mut() {
+                namespace::strip_key(prefix, &mut kv.key);
+            }
+            if let Some(kv) = event.prev_kv.as_mut() {
+                namespace::strip_key(prefix, &mut kv.key);
+            }
+        }
+    }
 }
 
 impl From<WatchResponse> for EtcdWatchResponse {
@@ -35,6 +35,19 @@ async fn success<R: Send>(response: R, sink: UnarySink<R>) {
         .await;
 }
 
+/// Help function to send failure `gRPC` response in an async task
+async fn failure<R>(sink: UnarySink<R>, rsc: RpcStatusCode, details: String) {
+    debug_assert_ne!(
+        rsc,
+        RpcStatusCode::OK,
+        "the input RpcStatusCode should not be OK"
+    );
+    sink.fail(RpcStatus::with_message(rsc, details))
+        .map_err(|e| error!("failed to send response, the error is: {:?}", e))
+        .map(|_| ())
+        .await;
+}
+
 /// Send failure `gRPC` response
 fn fail<R>(ctx: &RpcContext, sink: UnarySink<R>, rsc: RpcStatusCode, details: String) {
     debug_assert_ne!(
@@ -128,6 +141,8 @@ struct MockEtcdInner {
     watch_id_counter: AtomicI64,
     /// Revision of etcd
     revision: AtomicI64,
+    /// Compacted revision of etcd, `MockEtcd` keeps no history so only the revision is recorded
+    compact_revision: AtomicI64,
 }
 
 /// A locked `DuplexSink` for watch response
@@ -147,6 +162,7 @@ impl MockEtcdInner {
             lock_map: HashSet::new(),
             watch_id_counter: AtomicI64::new(0),
             revision: AtomicI64::new(0),
+            compact_revision: AtomicI64::new(0),
         }
     }
 
@@ -514,16 +530,43 @@ impl Kv for MockEtcd {
 
     fn compact(
         &mut self,
-        ctx: RpcContext,
-        _req: CompactionRequest,
+        _ctx: RpcContext,
+        req: CompactionRequest,
         sink: UnarySink<CompactionResponse>,
     ) {
-        fail(
-            &ctx,
-            sink,
-            RpcStatusCode::UNIMPLEMENTED,
-            "Not Implemented".to_owned(),
-        );
+        debug!("Receive compact request revision={}", req.get_revision());
+        let inner_clone = Arc::<Mutex<MockEtcdInner>>::clone(&self.inner);
+        let task = async move {
+            let inner = inner_clone.lock().await;
+            let revision = inner.revision.load(Ordering::Relaxed);
+            if req.get_revision() > revision {
+                drop(inner);
+                failure(
+                    sink,
+                    RpcStatusCode::OUT_OF_RANGE,
+                    "etcdserver: mvcc: required revision is a future revision".to_owned(),
+                )
+                .await;
+            } else if req.get_revision() <= inner.compact_revision.load(Ordering::Relaxed) {
+                drop(inner);
+                failure(
+                    sink,
+                    RpcStatusCode::OUT_OF_RANGE,
+                    "etcdserver: mvcc: required revision has been compacted".to_owned(),
+                )
+                .await;
+            } else {
+                inner
+                    .compact_revision
+                    .store(req.get_revision(), Ordering::Relaxed);
+                drop(inner);
+                let mut response = CompactionResponse::new();
+                let header = response.mut_header();
+                header.set_revision(revision);
+                success(response, sink).await;
+            }
+        };
+        smol::spawn(task).detach();
     }
 }
 